@@ -0,0 +1,143 @@
+// turns the NES 2.0 database (data/nes20db.xml) into the GAMES table included by
+// src/gamedb.rs. entries are matched on the <rom> checksums, which cover the prg rom
+// followed by the chr rom, the same data Rom hashes.
+// https://www.nesdev.org/wiki/NES_2.0#Database
+
+use std::fmt::Write;
+use std::path::Path;
+
+const DATABASE: &str = "data/nes20db.xml";
+
+const TIMING: [&str; 4] = ["NTSC", "PAL", "MULTI_REGION", "DENDY"];
+
+struct Game {
+    title: String,
+    crc32: u32,
+    sha1: String,
+    mapper: u16,
+    submapper: u8,
+    mirroring: &'static str,
+    battery: bool,
+    prg_ram_size: usize,
+    prg_nvram_size: usize,
+    chr_ram_size: usize,
+    chr_nvram_size: usize,
+    timing: &'static str,
+}
+
+// the value of `name` on the first `<tag .../>` element in `game`
+fn attribute<'a>(game: &'a str, tag: &str, name: &str) -> Option<&'a str> {
+    let start = game.find(&format!("<{tag} "))?;
+    let element = &game[start..start + game[start..].find('>')?];
+    let value = &element[element.find(&format!(" {name}=\""))? + name.len() + 3..];
+    Some(&value[..value.find('"')?])
+}
+
+fn number<T: std::str::FromStr>(game: &str, tag: &str, name: &str) -> Result<T, String> {
+    match attribute(game, tag, name) {
+        None => "0",
+        Some(value) => value,
+    }
+    .parse()
+    .map_err(|_| format!("bad {tag} {name} in {game}"))
+}
+
+// each game starts with a comment holding the path of the file it came from
+fn title(game: &str) -> String {
+    let Some(start) = game.find("<!--") else {
+        return String::new();
+    };
+    let comment = &game[start + 4..];
+    let path = comment[..comment.find("-->").unwrap_or(comment.len())].trim();
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    name.rsplit_once('.')
+        .map_or(name, |(stem, _)| stem)
+        .to_string()
+}
+
+fn parse_game(game: &str) -> Result<Option<Game>, String> {
+    let (Some(crc32), Some(sha1)) = (
+        attribute(game, "rom", "crc32"),
+        attribute(game, "rom", "sha1"),
+    ) else {
+        return Ok(None);
+    };
+    if attribute(game, "pcb", "mapper").is_none() {
+        return Ok(None);
+    }
+    let mirroring = match attribute(game, "pcb", "mirroring").unwrap_or("H") {
+        "V" => "VERTICAL",
+        "4" => "FOUR_SCREEN",
+        "1" => "SINGLE_SCREEN_LOWER",
+        _ => "HORIZONTAL",
+    };
+    let region: usize = number(game, "console", "region")?;
+    Ok(Some(Game {
+        title: title(game),
+        crc32: u32::from_str_radix(crc32, 16).map_err(|_| format!("bad crc32 {crc32}"))?,
+        sha1: sha1.to_lowercase(),
+        mapper: number(game, "pcb", "mapper")?,
+        submapper: number(game, "pcb", "submapper")?,
+        mirroring,
+        battery: attribute(game, "pcb", "battery") == Some("1"),
+        prg_ram_size: number(game, "prgram", "size")?,
+        prg_nvram_size: number(game, "prgnvram", "size")?,
+        chr_ram_size: number(game, "chrram", "size")?,
+        chr_nvram_size: number(game, "chrnvram", "size")?,
+        timing: TIMING.get(region).ok_or(format!("bad region {region}"))?,
+    }))
+}
+
+fn parse(xml: &str) -> Result<Vec<Game>, String> {
+    let mut games = Vec::new();
+    for game in xml.split("<game>").skip(1) {
+        let game = &game[..game.find("</game>").ok_or("unterminated <game>")?];
+        games.extend(parse_game(game)?);
+    }
+    games.sort_by_key(|game| game.crc32);
+    Ok(games)
+}
+
+fn size(size: usize) -> String {
+    if size == 0 {
+        "0".to_string()
+    } else {
+        format!("{size:#x}")
+    }
+}
+
+fn table(games: &[Game]) -> String {
+    let mut out = String::from("static GAMES: &[Game] = &[\n");
+    for game in games {
+        writeln!(
+            out,
+            "    Game {{ title: {:?}, crc32: {:#010x}, sha1: {:?}, mapper: {}, submapper: {}, \
+             mirroring: Mirroring::{}, battery: {}, prg_ram_size: {}, prg_nvram_size: {}, \
+             chr_ram_size: {}, chr_nvram_size: {}, timing: Timing::{} }},",
+            game.title,
+            game.crc32,
+            game.sha1,
+            game.mapper,
+            game.submapper,
+            game.mirroring,
+            game.battery,
+            size(game.prg_ram_size),
+            size(game.prg_nvram_size),
+            size(game.chr_ram_size),
+            size(game.chr_nvram_size),
+            game.timing,
+        )
+        .unwrap();
+    }
+    out.push_str("];\n");
+    out
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed={DATABASE}");
+    let xml = std::fs::read_to_string(DATABASE)
+        .unwrap_or_else(|e| panic!("Couldn't read {DATABASE}: {e}"));
+    let games = parse(&xml).unwrap_or_else(|e| panic!("{DATABASE}: {e}"));
+    let out = Path::new(&std::env::var("OUT_DIR").unwrap()).join("games.rs");
+    std::fs::write(out, table(&games)).unwrap();
+}
@@ -1,63 +1,207 @@
-use crate::{cartridge::Rom, cpu::Mem, ppu::PPU};
+use crate::cartridge::Rom;
+use crate::cpu::Mem;
+use crate::joypad::Joypad;
+use crate::mapper::{self, SharedMapper};
+use crate::ppu::PPU;
+use crate::region::Region;
 
 const RAM: u16 = 0x0000;
 const RAM_MIRROR_END: u16 = 0x1FFF;
 const PPU_REGISTERS: u16 = 0x2000;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const CARTRIDGE_SPACE: u16 = 0x4020;
+//$7000 in the prg ram mapped at $6000 on power on
+const TRAINER_OFFSET: usize = 0x1000;
 
 // ram mirrored 3 times
 // [0x800 .. 0x1000]
 // [0x1000 .. 0x1800]
 // [0x1800 .. 0x2000]
 
-pub struct Bus {
+type GameloopCallback<'call> = Box<dyn FnMut(&PPU, &mut Joypad) + 'call>;
+
+pub struct Bus<'call> {
     cpu_vram: [u8; 0x800],
-    prg_rom: Vec<u8>,
+    mapper: SharedMapper,
+    battery: bool,
+    trainer: Option<Vec<u8>>,
     ppu: PPU,
+    joypad1: Joypad,
+    joypad2: Joypad,
+    //last value read or written by the cpu, which unmapped and write-only addresses return
+    data_bus: u8,
+
+    cycles: usize,
+    frames: u64,
+    //leftover fraction of a ppu dot, for regions where a cpu cycle isn't a whole number of dots
+    ppu_dot_remainder: u32,
+    gameloop_callback: GameloopCallback<'call>,
 }
 
-impl Bus {
-    pub fn new(rom: Rom) -> Self {
+impl<'a> Bus<'a> {
+    pub fn new(rom: Rom) -> Bus<'a> {
+        Bus::new_with_callback(rom, |_, _| {})
+    }
+
+    //rom must come from Rom::new, which only accepts mappers `mapper::from_rom` can build.
+    //the callback is invoked once per frame, as vblank starts and the finished picture
+    //is in the ppu's frame, and gets to update the first controller's buttons
+    pub fn new_with_callback<F>(rom: Rom, gameloop_callback: F) -> Bus<'a>
+    where
+        F: FnMut(&PPU, &mut Joypad) + 'a,
+    {
+        let region = rom.region;
+        let battery = rom.battery;
+        let trainer = rom.trainer.clone();
+        let mapper = mapper::from_rom(rom).unwrap();
+        let mut ppu = PPU::new(mapper.clone());
+        ppu.set_region(region);
+
         Bus {
             cpu_vram: [0; 0x800],
-            prg_rom: rom.prg_rom,
-            ppu: PPU::new(rom.chr_rom, rom.screen_mirroring),
+            mapper,
+            battery,
+            trainer,
+            ppu,
+            joypad1: Joypad::new(),
+            joypad2: Joypad::new(),
+            data_bus: 0,
+            cycles: 0,
+            frames: 0,
+            ppu_dot_remainder: 0,
+            gameloop_callback: Box::from(gameloop_callback),
         }
     }
 
-    fn read_prg_rom(&self, mut addr: u16) -> u8 {
-        addr -= 0x8000;
-        if self.prg_rom.len() == 0x4000 && addr >= 0x4000 {
-            addr = addr % 0x4000;
+    // called by the cpu on power on and reset. a trainer goes back into prg ram at
+    // $7000-$71FF each time, as the copier that ran these dumps would load it
+    pub fn reset(&mut self) {
+        if let Some(trainer) = &self.trainer {
+            let mut mapper = self.mapper.borrow_mut();
+            let ram = mapper.prg_ram_mut();
+            if let Some(target) = ram.get_mut(TRAINER_OFFSET..TRAINER_OFFSET + trainer.len()) {
+                target.copy_from_slice(trainer);
+            }
         }
-        self.prg_rom[addr as usize]
+    }
+
+    pub fn ppu(&self) -> &PPU {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut PPU {
+        &mut self.ppu
+    }
+
+    pub fn joypad1_mut(&mut self) -> &mut Joypad {
+        &mut self.joypad1
+    }
+
+    pub fn joypad2_mut(&mut self) -> &mut Joypad {
+        &mut self.joypad2
+    }
+
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    // number of frames completed since power on. bumped as each vblank starts, just
+    // before the frame callback runs
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // a copy of the cartridge's prg ram if it is battery backed, which is what goes
+    // into save files
+    pub fn battery_ram(&self) -> Option<Vec<u8>> {
+        let mapper = self.mapper.borrow();
+        let ram = mapper.prg_ram();
+        (self.battery && !ram.is_empty()).then(|| ram.to_vec())
+    }
+
+    // restores battery backed ram from a save. a save of a different size fills what it can
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        let mut mapper = self.mapper.borrow_mut();
+        let ram = mapper.prg_ram_mut();
+        let len = ram.len().min(data.len());
+        ram[..len].copy_from_slice(&data[..len]);
+    }
+
+    pub fn region(&self) -> Region {
+        self.ppu.region()
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.ppu.set_region(region);
+        self.ppu_dot_remainder = 0;
+    }
+
+    //the ppu runs three dots for every cpu cycle on ntsc and dendy, 3.2 on pal
+    pub fn tick(&mut self, cycles: u8) {
+        self.cycles += cycles as usize;
+        let (numerator, denominator) = self.ppu.region().ppu_dots_per_cpu_cycle();
+        self.ppu_dot_remainder += cycles as u32 * numerator;
+        let dots = self.ppu_dot_remainder / denominator;
+        self.ppu_dot_remainder %= denominator;
+
+        {
+            let mut mapper = self.mapper.borrow_mut();
+            for _ in 0..cycles {
+                mapper.cpu_cycle();
+            }
+        }
+
+        let new_frame = self.ppu.tick(dots as u8);
+        if new_frame {
+            self.frames += 1;
+            (self.gameloop_callback)(&self.ppu, &mut self.joypad1);
+        }
+    }
+
+    pub fn poll_nmi_status(&mut self) -> Option<u8> {
+        self.ppu.poll_nmi_interrupt()
+    }
+
+    //level triggered, the cartridge keeps it asserted until the game acknowledges it
+    pub fn poll_irq_status(&self) -> bool {
+        self.mapper.borrow().irq()
     }
 }
 
-impl Mem for Bus {
+impl Mem for Bus<'_> {
     fn mem_read(&mut self, addr: u16) -> u8 {
-        match addr {
+        let data = match addr {
             RAM..=RAM_MIRROR_END => {
                 let mirror_down_addr = addr & 0b00000111_11111111;
                 self.cpu_vram[mirror_down_addr as usize]
             }
-            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 | 0x4014 => {
-                panic!("Attempt to read from write-only PPU address {:x}", addr);
-            }
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 => self.ppu.read_io_latch(),
+            //open bus, usually the high byte of the instruction's own operand
+            0x4014 => self.data_bus,
+            0x2002 => self.ppu.read_status(),
+            0x2004 => self.ppu.read_oam_data(),
             0x2007 => self.ppu.read_data(),
             0x2008..=PPU_REGISTERS_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b00100000_00000111;
                 self.mem_read(mirror_down_addr)
             }
-            0x8000..=0xFFFF => self.read_prg_rom(addr),
+            0x4016 => self.joypad1.read(),
+            0x4017 => self.joypad2.read(),
+            CARTRIDGE_SPACE..=0xFFFF => self.mapper.borrow_mut().cpu_read(addr),
             _ => {
                 println!("Ignore mem access at {}", addr);
                 0
             }
-        }
+        };
+        self.data_bus = data;
+        data
     }
 
     fn mem_write(&mut self, addr: u16, data: u8) {
+        self.data_bus = data;
+        if (PPU_REGISTERS..=0x2007).contains(&addr) {
+            self.mapper.borrow_mut().ppu_register_write(addr, data);
+        }
         match addr {
             RAM..=RAM_MIRROR_END => {
                 let mirror_down_addr = addr & 0b11111111111;
@@ -66,6 +210,18 @@ impl Mem for Bus {
             0x2000 => {
                 self.ppu.write_to_ctrl(data);
             }
+            0x2001 => {
+                self.ppu.write_to_mask(data);
+            }
+            0x2003 => {
+                self.ppu.write_to_oam_addr(data);
+            }
+            0x2004 => {
+                self.ppu.write_to_oam_data(data);
+            }
+            0x2005 => {
+                self.ppu.write_to_scroll(data);
+            }
 
             0x2006 => {
                 self.ppu.write_to_ppu_addr(data);
@@ -78,8 +234,27 @@ impl Mem for Bus {
                 let mirror_down_addr = addr & 0b00100000_00000111;
                 self.mem_write(mirror_down_addr, data);
             }
-            0x8000..=0xFFFF => {
-                panic!("Attempt to write to Cartridge ROM space: {:x}", addr)
+            // https://wiki.nesdev.com/w/index.php/PPU_programmer_reference#OAM_DMA_.28.244014.29_.3E_write
+            0x4014 => {
+                let mut buffer: [u8; 256] = [0; 256];
+                let hi: u16 = (data as u16) << 8;
+                for (i, byte) in buffer.iter_mut().enumerate() {
+                    *byte = self.mem_read(hi + i as u16);
+                }
+                self.ppu.write_oam_dma(&buffer);
+
+                //the cpu is halted for 513 cycles while the transfer runs
+                for _ in 0..513 {
+                    self.tick(1);
+                }
+            }
+            //strobe goes to both controllers
+            0x4016 => {
+                self.joypad1.write(data);
+                self.joypad2.write(data);
+            }
+            CARTRIDGE_SPACE..=0xFFFF => {
+                self.mapper.borrow_mut().cpu_write(addr, data);
             }
             _ => {
                 println!("Ignore mem write-access at {}", addr);
@@ -87,3 +262,69 @@ impl Mem for Bus {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::mapper::test_rom;
+
+    #[test]
+    fn test_battery_ram_round_trip() {
+        let mut rom = test_rom(1, 2, 1);
+        rom.battery = true;
+        let mut bus = Bus::new(rom);
+        bus.load_battery_ram(&[0x11, 0x22]);
+        assert_eq!(bus.mem_read(0x6001), 0x22);
+
+        bus.mem_write(0x6002, 0x33);
+        let ram = bus.battery_ram().unwrap();
+        assert_eq!(ram.len(), 0x2000);
+        assert_eq!(&ram[..3], &[0x11, 0x22, 0x33]);
+    }
+
+    #[test]
+    fn test_reset_copies_trainer_to_7000() {
+        let mut rom = test_rom(0, 1, 1);
+        rom.trainer = Some((0..=255).cycle().take(512).collect());
+        let mut bus = Bus::new(rom);
+        assert_eq!(bus.mem_read(0x7001), 0);
+
+        bus.reset();
+        assert_eq!(bus.mem_read(0x6fff), 0);
+        assert_eq!(bus.mem_read(0x7001), 1);
+        assert_eq!(bus.mem_read(0x71ff), 0xff);
+        assert_eq!(bus.mem_read(0x7200), 0);
+    }
+
+    #[test]
+    fn test_no_battery_ram_without_battery() {
+        assert_eq!(Bus::new(test_rom(1, 2, 1)).battery_ram(), None);
+        //discrete boards have ram at $6000 too, which a battery flag makes saved
+        let mut rom = test_rom(2, 2, 1);
+        rom.battery = true;
+        assert_eq!(
+            Bus::new(rom).battery_ram().map(|ram| ram.len()),
+            Some(0x2000)
+        );
+    }
+
+    #[test]
+    fn test_4014_reads_open_bus() {
+        let mut bus = Bus::new(test_rom(0, 1, 1));
+        bus.mem_write(0x0010, 0x33);
+        assert_eq!(bus.mem_read(0x4014), 0x33);
+
+        bus.mem_write(0x0011, 0x44);
+        bus.mem_read(0x0010);
+        assert_eq!(bus.mem_read(0x4014), 0x33);
+    }
+
+    #[test]
+    fn test_lda_4014_reads_operand_high_byte() {
+        let mut cpu = CPU::new(Bus::new(test_rom(0, 1, 1)));
+        //LDA $4014; BRK
+        cpu.load_and_run(vec![0xad, 0x14, 0x40, 0x00]);
+        assert_eq!(cpu.register_a, 0x40);
+    }
+}
@@ -1,58 +1,733 @@
+use crate::checksum::{crc32, sha1};
+use crate::gamedb::{self, Game};
+use crate::mapper::SUPPORTED_MAPPERS;
+use crate::region::Region;
+
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const HEADER_SIZE: usize = 16;
+const DISK_DUDE: &[u8] = b"DiskDude!";
 const PGR_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
+const CHR_RAM_SIZE: usize = 8192;
+const PRG_RAM_PAGE_SIZE: usize = 8192;
+const PRG_ROM_BANK_SIZE: usize = 8192;
+const TRAINER_SIZE: usize = 512;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 #[allow(non_camel_case_types)]
 pub enum Mirroring {
     VERTICAL,
     HORIZONTAL,
+    // all four nametables map onto one 1KB page of the console's vram
+    SINGLE_SCREEN_LOWER,
+    SINGLE_SCREEN_UPPER,
+    // the cartridge supplies 2KB of extra vram so every nametable is distinct
     FOUR_SCREEN,
 }
 
-pub struct Rom {
-    pub prg_rom: Vec<u8>,
-    pub chr_rom: Vec<u8>,
-    pub mapper: u8,
-    pub screen_mirroring: Mirroring,
+// why a file couldn't be loaded as a rom
+#[derive(Debug, PartialEq, Clone)]
+pub enum RomError {
+    // doesn't start with "NES\x1a"
+    BadMagic,
+    // shorter than its header says, in bytes
+    Truncated { expected: usize, actual: usize },
+    UnsupportedMapper(u16),
+    // header versions other than iNES 1.0 and NES 2.0
+    UnsupportedFormat,
+    // no prg rom, or a size that isn't whole 8KB banks, in bytes
+    BadPrgRomSize(usize),
 }
 
-impl Rom {
-    pub fn new(raw: &Vec<u8>) -> Result<Rom, String> {
-        if &raw[0..4] != NES_TAG {
-            return Err("File is not in iNES format".to_string());
+impl std::fmt::Display for RomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RomError::BadMagic => write!(f, "File is not in iNES format"),
+            RomError::Truncated { expected, actual } => write!(
+                f,
+                "File is truncated: the header describes {expected} bytes but there are {actual}"
+            ),
+            RomError::UnsupportedMapper(mapper) => write!(f, "Mapper {mapper} is not supported"),
+            RomError::UnsupportedFormat => write!(f, "Unrecognised iNES header version"),
+            RomError::BadPrgRomSize(size) => write!(
+                f,
+                "PRG ROM of {size} bytes can't be banked, it must be a non-zero multiple of 8KB"
+            ),
         }
+    }
+}
 
-        //mapper: upper 4 bits of byte6 serves as lower bits
-        //and upper 4 bits of byte7 serves as upper bits of ROM Mapper type
-        let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
+impl std::error::Error for RomError {}
+
+// a header field the game database disagrees with, and was overridden by it
+#[derive(Debug, PartialEq, Clone)]
+pub struct HeaderCorrection {
+    pub field: &'static str,
+    pub header: String,
+    pub database: String,
+}
+
+impl std::fmt::Display for HeaderCorrection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is {}, the header says {}",
+            self.field, self.database, self.header
+        )
+    }
+}
+
+// the 16 byte header in front of the rom data, as the file states it.
+// https://www.nesdev.org/wiki/INES
+// https://www.nesdev.org/wiki/NES_2.0
+#[derive(Debug, PartialEq, Clone)]
+pub struct RomHeader {
+    pub format: HeaderFormat,
+    // 12 bits in NES 2.0, 8 in iNES 1.0
+    pub mapper: u16,
+    // tells apart boards that share a mapper number. always 0 for iNES 1.0
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    // volatile and battery backed ram sizes. iNES 1.0 only gives one prg ram size,
+    // which counts as nvram when the battery flag is set, and never mentions chr ram
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub timing: Timing,
+    pub console_type: ConsoleType,
+    // NES 2.0 only, the rest are 0 for iNES 1.0
+    pub misc_roms: u8,
+    pub expansion_device: u8,
+    // bytes 7-15 held the "DiskDude!" signature an old dumping tool left in many
+    // iNES files, and were read as zeros
+    pub disk_dude: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum HeaderFormat {
+    INES,
+    NES2,
+}
+
+// cpu/ppu timing the rom was made for
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum Timing {
+    NTSC,
+    PAL,
+    // runs on either
+    MULTI_REGION,
+    DENDY,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum ConsoleType {
+    NES,
+    // the arcade boards, with the ppu variant (byte 13 low nibble) and the hardware
+    // type (high nibble), both 0 when iNES 1.0 doesn't say
+    VS_SYSTEM { ppu: u8, hardware: u8 },
+    PLAYCHOICE_10,
+    // NES 2.0 extended console types, from byte 13: famiclones, vt chips and so on
+    EXTENDED(u8),
+}
+
+impl Timing {
+    pub fn region(&self) -> Region {
+        match self {
+            Timing::PAL => Region::PAL,
+            Timing::DENDY => Region::DENDY,
+            Timing::NTSC | Timing::MULTI_REGION => Region::NTSC,
+        }
+    }
+}
 
-        let ines_ver = (raw[7] >> 2) & 0b11;
-        if ines_ver != 0 {
-            return Err("iNES2.0 format not supported".to_string());
+impl RomHeader {
+    pub fn parse(raw: &[u8]) -> Result<RomHeader, RomError> {
+        if raw.len() < HEADER_SIZE {
+            //too short to be anything, a header would at least start with the tag
+            if !NES_TAG.starts_with(&raw[..raw.len().min(NES_TAG.len())]) {
+                return Err(RomError::BadMagic);
+            }
+            return Err(RomError::Truncated {
+                expected: HEADER_SIZE,
+                actual: raw.len(),
+            });
         }
+        if raw[0..4] != NES_TAG {
+            return Err(RomError::BadMagic);
+        }
+
+        //the signature's 'D' makes byte 7 look like an unknown header version with
+        //a mapper high nibble of 4, so it is blanked out before anything is read
+        let disk_dude = &raw[7..HEADER_SIZE] == DISK_DUDE;
+        let mut clean = [0; HEADER_SIZE];
+        let used = if disk_dude { 7 } else { HEADER_SIZE };
+        clean[..used].copy_from_slice(&raw[..used]);
+        let raw = &clean;
+
+        //bits 2-3 of byte 7 are 0b10 in an NES 2.0 header, which extends iNES in bytes 8-15
+        let format = match (raw[7] >> 2) & 0b11 {
+            0 => HeaderFormat::INES,
+            2 => HeaderFormat::NES2,
+            _ => return Err(RomError::UnsupportedFormat),
+        };
+        let nes2 = format == HeaderFormat::NES2;
+
+        //mapper: upper 4 bits of byte6 serves as lower bits
+        //and upper 4 bits of byte7 serves as upper bits of ROM Mapper type.
+        //NES 2.0 adds 4 more bits in byte 8, and a submapper number above them
+        let mapper_low = ((raw[7] & 0b1111_0000) | (raw[6] >> 4)) as u16;
+        let (mapper, submapper) = if nes2 {
+            (((raw[8] & 0b1111) as u16) << 8 | mapper_low, raw[8] >> 4)
+        } else {
+            (mapper_low, 0)
+        };
 
         let four_screen = raw[6] & 0b1000 != 0;
         let vertical_mirroring = raw[6] & 0b1 != 0;
-        let screen_mirroring = match (four_screen, vertical_mirroring) {
+        let mirroring = match (four_screen, vertical_mirroring) {
             (true, _) => Mirroring::FOUR_SCREEN,
             (false, true) => Mirroring::VERTICAL,
             (false, false) => Mirroring::HORIZONTAL,
         };
+        let battery = raw[6] & 0b10 != 0;
+        let trainer = raw[6] & 0b100 != 0;
+
+        //byte 7 bits 0-1 flag vs. system and playchoice roms in both formats, NES 2.0
+        //describes them further in byte 13
+        let console_type = match raw[7] & 0b11 {
+            0 => ConsoleType::NES,
+            1 if nes2 => ConsoleType::VS_SYSTEM {
+                ppu: raw[13] & 0b1111,
+                hardware: raw[13] >> 4,
+            },
+            1 => ConsoleType::VS_SYSTEM {
+                ppu: 0,
+                hardware: 0,
+            },
+            3 if nes2 => ConsoleType::EXTENDED(raw[13] & 0b1111),
+            //bit 1 is the playchoice flag in iNES 1.0
+            _ => ConsoleType::PLAYCHOICE_10,
+        };
+
+        if !nes2 {
+            //byte 8 is rarely filled in, 0 is taken to mean the usual 8KB.
+            //only ntsc and pal are told apart, in bit 0 of byte 9
+            let prg_ram_size = raw[8].max(1) as usize * PRG_RAM_PAGE_SIZE;
+            let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+            return Ok(RomHeader {
+                format,
+                mapper,
+                submapper,
+                prg_rom_size: raw[4] as usize * PGR_ROM_PAGE_SIZE,
+                chr_rom_size,
+                prg_ram_size: if battery { 0 } else { prg_ram_size },
+                prg_nvram_size: if battery { prg_ram_size } else { 0 },
+                chr_ram_size: if chr_rom_size == 0 { CHR_RAM_SIZE } else { 0 },
+                chr_nvram_size: 0,
+                mirroring,
+                battery,
+                trainer,
+                timing: if raw[9] & 0b1 != 0 {
+                    Timing::PAL
+                } else {
+                    Timing::NTSC
+                },
+                console_type,
+                misc_roms: 0,
+                expansion_device: 0,
+                disk_dude,
+            });
+        }
+
+        let timing = match raw[12] & 0b11 {
+            0 => Timing::NTSC,
+            1 => Timing::PAL,
+            2 => Timing::MULTI_REGION,
+            _ => Timing::DENDY,
+        };
+
+        Ok(RomHeader {
+            format,
+            mapper,
+            submapper,
+            prg_rom_size: nes2_rom_size(raw[4], raw[9] & 0b1111, PGR_ROM_PAGE_SIZE),
+            chr_rom_size: nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE),
+            //ram sizes as shift counts, volatile in the low nibble and nvram in the high one
+            prg_ram_size: shift_size(raw[10] & 0b1111),
+            prg_nvram_size: shift_size(raw[10] >> 4),
+            chr_ram_size: shift_size(raw[11] & 0b1111),
+            chr_nvram_size: shift_size(raw[11] >> 4),
+            mirroring,
+            battery,
+            trainer,
+            timing,
+            console_type,
+            misc_roms: raw[14] & 0b11,
+            expansion_device: raw[15] & 0b11_1111,
+            disk_dude,
+        })
+    }
+}
+
+// a rom ready to run: its data, and the settings the emulator uses for it. those
+// come from the header, corrected by the game database when it knows the rom
+pub struct Rom {
+    pub header: RomHeader,
+    // checksums of the prg rom followed by the chr rom, which identify the game
+    // however its header was written
+    pub crc32: u32,
+    pub sha1: [u8; 20],
+    pub game: Option<&'static Game>,
+    // where the database overrode the header
+    pub corrections: Vec<HeaderCorrection>,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    // boards without chr rom carry writable chr ram instead, held in chr_rom
+    pub chr_ram: bool,
+    // work ram at $6000-$7FFF. boards with more than 8KB bank it in 8KB pages
+    pub prg_ram_size: usize,
+    // the prg ram is battery backed and holds the game's saves
+    pub battery: bool,
+    // 512 bytes some dumps and hacks expect at $7000-$71FF, put there by the copier
+    // device that originally ran them
+    pub trainer: Option<Vec<u8>>,
+    pub mapper: u8,
+    pub submapper: u8,
+    pub screen_mirroring: Mirroring,
+    pub region: Region,
+}
+
+impl Rom {
+    pub fn new(raw: &[u8]) -> Result<Rom, RomError> {
+        let header = RomHeader::parse(raw)?;
+        //every board maps prg rom in 8KB units at the smallest, and there's nothing to
+        //run without any
+        if header.prg_rom_size == 0 || header.prg_rom_size % PRG_ROM_BANK_SIZE != 0 {
+            return Err(RomError::BadPrgRomSize(header.prg_rom_size));
+        }
+
+        let trainer_size = if header.trainer { TRAINER_SIZE } else { 0 };
+        let prg_rom_start = HEADER_SIZE + trainer_size;
+        let chr_rom_start = prg_rom_start.saturating_add(header.prg_rom_size);
+        //NES 2.0 exponent sizes can be absurdly large, saturating keeps them comparable
+        let expected = chr_rom_start.saturating_add(header.chr_rom_size);
+        //a few dumps carry extra bytes past the chr rom (title data), which are ignored
+        if raw.len() < expected {
+            return Err(RomError::Truncated {
+                expected,
+                actual: raw.len(),
+            });
+        }
+
+        let data = &raw[prg_rom_start..expected];
+        let (crc32, sha1) = (crc32(data), sha1(data));
+        let game = gamedb::find(crc32, &sha1);
+        let (settings, corrections) = match game {
+            Some(game) => correct(&header, game),
+            None => (header.clone(), Vec::new()),
+        };
 
-        let prg_rom_size = raw[4] as usize * PGR_ROM_PAGE_SIZE;
-        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+        let mapper = match u8::try_from(settings.mapper) {
+            Ok(mapper) if SUPPORTED_MAPPERS.contains(&mapper) => mapper,
+            _ => return Err(RomError::UnsupportedMapper(settings.mapper)),
+        };
 
-        let skip_trainer = raw[6] & 0b100 != 0;
+        let trainer = header
+            .trainer
+            .then(|| raw[HEADER_SIZE..HEADER_SIZE + TRAINER_SIZE].to_vec());
 
-        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
-        let chr_rom_start = prg_rom_start + prg_rom_size;
+        //NES 2.0 headers should give the chr ram size, but plenty leave it at 0
+        let chr_ram = header.chr_rom_size == 0;
+        let chr_rom = if chr_ram {
+            let size = settings.chr_ram_size + settings.chr_nvram_size;
+            vec![0; if size == 0 { CHR_RAM_SIZE } else { size }]
+        } else {
+            raw[chr_rom_start..expected].to_vec()
+        };
 
         Ok(Rom {
-            prg_rom: raw[prg_rom_start..prg_rom_start + prg_rom_size].to_vec(),
-            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom,
+            chr_ram,
+            prg_ram_size: settings.prg_ram_size + settings.prg_nvram_size,
+            battery: settings.battery,
+            trainer,
             mapper,
-            screen_mirroring,
+            submapper: settings.submapper,
+            screen_mirroring: settings.mirroring,
+            region: settings.timing.region(),
+            header,
+            crc32,
+            sha1,
+            game,
+            corrections,
         })
     }
+
+    pub fn title(&self) -> Option<&'static str> {
+        self.game.map(|game| game.title)
+    }
+}
+
+// the header with the database's values filled in, and the fields that changed.
+// NES 2.0 headers are trusted as they are, most were written from the database in
+// the first place and it can lag behind newer findings. only the fields the header
+// can hold are reported, iNES 1.0 has no way to say how much chr ram a board has
+fn correct(header: &RomHeader, game: &Game) -> (RomHeader, Vec<HeaderCorrection>) {
+    if header.format == HeaderFormat::NES2 {
+        return (header.clone(), Vec::new());
+    }
+
+    let mut corrections = Vec::new();
+    let mut check = |field, header: String, database: String| {
+        if header != database {
+            corrections.push(HeaderCorrection {
+                field,
+                header,
+                database,
+            });
+        }
+    };
+    check("mapper", header.mapper.to_string(), game.mapper.to_string());
+    check(
+        "mirroring",
+        format!("{:?}", header.mirroring),
+        format!("{:?}", game.mirroring),
+    );
+    check(
+        "battery",
+        header.battery.to_string(),
+        game.battery.to_string(),
+    );
+    //iNES 1.0 can't say a rom runs on both, so that isn't a disagreement
+    if game.timing != Timing::MULTI_REGION {
+        check(
+            "timing",
+            format!("{:?}", header.timing),
+            format!("{:?}", game.timing),
+        );
+    }
+
+    let settings = RomHeader {
+        mapper: game.mapper,
+        submapper: game.submapper,
+        mirroring: game.mirroring,
+        battery: game.battery,
+        prg_ram_size: game.prg_ram_size,
+        prg_nvram_size: game.prg_nvram_size,
+        chr_ram_size: game.chr_ram_size,
+        chr_nvram_size: game.chr_nvram_size,
+        timing: game.timing,
+        ..header.clone()
+    };
+    (settings, corrections)
+}
+
+// NES 2.0 ram sizes are stored as a shift count: 64 << n bytes, with 0 meaning none
+fn shift_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+// NES 2.0 rom sizes: the LSB byte from 4/5 plus 4 MSB bits from byte 9 count pages,
+// unless the MSB bits are all set, in which case the LSB byte holds an exponent and
+// multiplier: 2^E * (MM * 2 + 1) bytes
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> usize {
+    if msb == 0b1111 {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        2usize.saturating_pow(exponent).saturating_mul(multiplier)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * page_size
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(bytes: [u8; 12]) -> Vec<u8> {
+        let mut raw = vec![b'N', b'E', b'S', 0x1a];
+        raw.extend_from_slice(&bytes);
+        raw
+    }
+
+    #[test]
+    fn test_ines_header() {
+        let raw = header([2, 1, 0b0001_0011, 0b0100_0000, 0, 1, 0, 0, 0, 0, 0, 0]);
+        let header = RomHeader::parse(&raw).unwrap();
+        assert_eq!(header.format, HeaderFormat::INES);
+        assert_eq!(header.mapper, 0x41);
+        assert_eq!(header.prg_rom_size, 0x8000);
+        assert_eq!(header.chr_rom_size, 0x2000);
+        assert_eq!(header.mirroring, Mirroring::VERTICAL);
+        assert!(header.battery);
+        assert_eq!((header.prg_ram_size, header.prg_nvram_size), (0, 0x2000));
+        assert_eq!(header.chr_ram_size, 0);
+        assert_eq!(header.timing, Timing::PAL);
+        assert_eq!(header.console_type, ConsoleType::NES);
+    }
+
+    #[test]
+    fn test_nes2_header() {
+        let raw = header([
+            0x02,
+            0x00,
+            0b0001_1000,
+            0b0100_1001,
+            0x31,
+            0x01,
+            0x70,
+            0x09,
+            0x03,
+            0x24,
+            0x02,
+            0x2a,
+        ]);
+        let header = RomHeader::parse(&raw).unwrap();
+        assert_eq!(header.format, HeaderFormat::NES2);
+        assert_eq!((header.mapper, header.submapper), (0x141, 3));
+        assert_eq!(header.prg_rom_size, 0x102 * 0x4000);
+        assert_eq!(header.chr_rom_size, 0);
+        assert_eq!((header.prg_ram_size, header.prg_nvram_size), (0, 0x2000));
+        assert_eq!((header.chr_ram_size, header.chr_nvram_size), (0x8000, 0));
+        assert_eq!(header.mirroring, Mirroring::FOUR_SCREEN);
+        assert_eq!(header.timing, Timing::DENDY);
+        assert_eq!(
+            header.console_type,
+            ConsoleType::VS_SYSTEM {
+                ppu: 4,
+                hardware: 2
+            }
+        );
+        assert_eq!(header.misc_roms, 2);
+        assert_eq!(header.expansion_device, 0x2a);
+    }
+
+    #[test]
+    fn test_nes2_exponent_rom_size() {
+        //2^10 * (1 * 2 + 1)
+        assert_eq!(
+            nes2_rom_size(0b0010_1001, 0b1111, PGR_ROM_PAGE_SIZE),
+            3 * 1024
+        );
+        assert_eq!(
+            nes2_rom_size(0x02, 0b0001, PGR_ROM_PAGE_SIZE),
+            0x102 * 0x4000
+        );
+    }
+
+    #[test]
+    fn test_rom_uses_header_ram_sizes() {
+        //NES 2.0 nrom with no chr rom, 32KB of chr ram and 8KB of battery backed prg ram
+        let mut raw = header([1, 0, 0b10, 0x08, 0, 0, 0x70, 0x09, 0, 0, 0, 0]);
+        raw.resize(16 + 0x4000, 0);
+        let rom = Rom::new(&raw).unwrap();
+        assert!(rom.chr_ram);
+        assert_eq!(rom.chr_rom.len(), 0x8000);
+        assert_eq!(rom.prg_ram_size, 0x2000);
+        assert!(rom.battery);
+        assert_eq!(rom.header.format, HeaderFormat::NES2);
+    }
+
+    #[test]
+    fn test_short_files_are_errors() {
+        assert_eq!(
+            Rom::new(&[]).err(),
+            Some(RomError::Truncated {
+                expected: 16,
+                actual: 0
+            })
+        );
+        assert_eq!(
+            Rom::new(b"NES\x1a\x01").err(),
+            Some(RomError::Truncated {
+                expected: 16,
+                actual: 5
+            })
+        );
+        assert_eq!(Rom::new(b"PK").err(), Some(RomError::BadMagic));
+        assert_eq!(Rom::new(&[0; 64]).err(), Some(RomError::BadMagic));
+    }
+
+    #[test]
+    fn test_rom_data_shorter_than_header_says() {
+        //one 16KB prg bank, one 8KB chr bank and a trainer
+        let mut raw = header([1, 1, 0b100, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        raw.resize(16 + 512 + 0x4000, 0);
+        assert_eq!(
+            Rom::new(&raw).err(),
+            Some(RomError::Truncated {
+                expected: 16 + 512 + 0x6000,
+                actual: 16 + 512 + 0x4000
+            })
+        );
+
+        raw.resize(16 + 512 + 0x6000, 0);
+        assert!(Rom::new(&raw).is_ok());
+    }
+
+    #[test]
+    fn test_huge_nes2_sizes_are_truncated_not_overflowing() {
+        //prg of 2^62 bytes, chr of 2^63 * 7
+        let mut raw = header([0xf8, 0xff, 0, 0x08, 0, 0xff, 0, 0, 0, 0, 0, 0]);
+        raw.resize(0x100, 0);
+        assert!(matches!(
+            Rom::new(&raw),
+            Err(RomError::Truncated { actual: 0x100, .. })
+        ));
+    }
+
+    #[test]
+    fn test_rom_without_prg_is_rejected() {
+        let mut raw = header([0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        raw.resize(16 + 0x2000, 0);
+        assert_eq!(Rom::new(&raw).err(), Some(RomError::BadPrgRomSize(0)));
+    }
+
+    #[test]
+    fn test_prg_not_in_8kb_banks_is_rejected() {
+        //NES 2.0 exponent size: 2^10 * 3 bytes
+        let mut raw = header([0b0010_1001, 0, 0, 0x08, 0, 0x0f, 0, 0, 0, 0, 0, 0]);
+        raw.resize(16 + 3 * 1024, 0);
+        assert_eq!(
+            Rom::new(&raw).err(),
+            Some(RomError::BadPrgRomSize(3 * 1024))
+        );
+
+        //2^13 * 1, a single 8KB bank, is fine
+        let mut raw = header([0b0011_0100, 0, 0, 0x08, 0, 0x0f, 0, 0, 0, 0, 0, 0]);
+        raw.resize(16 + 0x2000, 0);
+        assert_eq!(Rom::new(&raw).unwrap().prg_rom.len(), 0x2000);
+    }
+
+    #[test]
+    fn test_unsupported_header_version() {
+        let raw = header([1, 1, 0, 0b0100, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            RomHeader::parse(&raw).err(),
+            Some(RomError::UnsupportedFormat)
+        );
+    }
+
+    #[test]
+    fn test_disk_dude_header_is_cleaned() {
+        let mut raw = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0x21];
+        raw.extend_from_slice(b"DiskDude!");
+        raw.resize(16 + 0x6000, 0);
+        let rom = Rom::new(&raw).unwrap();
+        assert!(rom.header.disk_dude);
+        assert_eq!(rom.mapper, 2);
+        assert_eq!(rom.header.format, HeaderFormat::INES);
+        assert_eq!(rom.prg_ram_size, 0x2000);
+    }
+
+    #[test]
+    fn test_error_messages() {
+        assert_eq!(
+            RomError::UnsupportedMapper(300).to_string(),
+            "Mapper 300 is not supported"
+        );
+        assert_eq!(
+            RomError::Truncated {
+                expected: 40976,
+                actual: 16
+            }
+            .to_string(),
+            "File is truncated: the header describes 40976 bytes but there are 16"
+        );
+    }
+
+    #[test]
+    fn test_rom_is_identified_by_its_data() {
+        let rom = Rom::new(&std::fs::read("nestest.nes").unwrap()).unwrap();
+        assert_eq!(rom.crc32, 0x158b_0388);
+        assert_eq!(rom.title(), Some("nestest"));
+        assert!(rom.corrections.is_empty());
+
+        let mut raw = header([1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        raw.resize(16 + 0x6000, 0);
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.title(), None);
+        assert_eq!(rom.crc32, crc32(&raw[16..]));
+    }
+
+    #[test]
+    fn test_bad_ines_header_on_known_dump_is_corrected() {
+        //nestest with its header claiming vertical mirroring, a battery and pal timing
+        let mut raw = std::fs::read("nestest.nes").unwrap();
+        raw[6] |= 0b11;
+        raw[9] |= 1;
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.title(), Some("nestest"));
+        assert_eq!(rom.header.mirroring, Mirroring::VERTICAL);
+        assert!(rom.header.battery);
+        assert_eq!(rom.screen_mirroring, Mirroring::HORIZONTAL);
+        assert!(!rom.battery);
+        assert_eq!(rom.region, Region::NTSC);
+        assert_eq!(
+            rom.corrections
+                .iter()
+                .map(|correction| correction.field)
+                .collect::<Vec<_>>(),
+            ["mirroring", "battery", "timing"]
+        );
+    }
+
+    #[test]
+    fn test_database_corrects_ines_headers() {
+        static GAME: Game = Game {
+            title: "Test",
+            crc32: 0,
+            sha1: "",
+            mapper: 4,
+            submapper: 1,
+            mirroring: Mirroring::VERTICAL,
+            battery: true,
+            prg_ram_size: 0,
+            prg_nvram_size: 0x2000,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            timing: Timing::MULTI_REGION,
+        };
+
+        //mapper 1, horizontal, no battery, pal
+        let ines = RomHeader::parse(&header([8, 16, 0x10, 0, 0, 1, 0, 0, 0, 0, 0, 0])).unwrap();
+        let (settings, corrections) = correct(&ines, &GAME);
+        assert_eq!((settings.mapper, settings.submapper), (4, 1));
+        assert_eq!(settings.mirroring, Mirroring::VERTICAL);
+        assert!(settings.battery);
+        assert_eq!(
+            (settings.prg_ram_size, settings.prg_nvram_size),
+            (0, 0x2000)
+        );
+        assert_eq!(settings.timing, Timing::MULTI_REGION);
+        assert_eq!(settings.prg_rom_size, ines.prg_rom_size);
+        assert_eq!(
+            corrections
+                .iter()
+                .map(|correction| correction.to_string())
+                .collect::<Vec<_>>(),
+            [
+                "mapper is 4, the header says 1",
+                "mirroring is VERTICAL, the header says HORIZONTAL",
+                "battery is true, the header says false",
+            ]
+        );
+
+        let nes2 = RomHeader::parse(&header([8, 16, 0x10, 0x08, 0, 0, 0, 0, 0, 0, 0, 0])).unwrap();
+        assert_eq!(correct(&nes2, &GAME), (nes2, Vec::new()));
+    }
 }
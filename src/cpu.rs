@@ -4,7 +4,7 @@ use crate::bus::Bus;
 use crate::opcodes::OPCODE_MAP;
 use bitflags::bitflags;
 
-use std::fs::OpenOptions;
+use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
 
 bitflags! {
@@ -20,20 +20,24 @@ bitflags! {
     }
 }
 
-pub struct CPU {
+pub struct CPU<'a> {
     pub register_a: u8,
     pub register_x: u8,
     pub register_y: u8,
     pub status: Flags,
     pub program_counter: u16,
     pub stack_ptr: u8,
-    pub bus: Bus,
+    pub bus: Bus<'a>,
+    //cycles the current instruction has already passed to the bus
+    cycles_ticked: u8,
 }
 
 const STACK: u16 = 0x0100;
 // stack initially set to 0x0100. during reset, cpu pushes program_counter(PC) and status register(P) values to the stack
 // resulting in a decrement of 3. as a result the stack pointer wraps back to 0x1fd
 const STACK_RESET: u8 = 0xfd;
+const NMI_VECTOR: u16 = 0xfffa;
+const IRQ_VECTOR: u16 = 0xfffe;
 
 #[derive(Debug)]
 #[allow(non_camel_case_types)]
@@ -68,7 +72,7 @@ pub trait Mem {
     }
 }
 
-impl Mem for CPU {
+impl Mem for CPU<'_> {
     fn mem_read(&mut self, addr: u16) -> u8 {
         self.bus.mem_read(addr)
     }
@@ -86,8 +90,8 @@ impl Mem for CPU {
     }
 }
 
-impl CPU {
-    pub fn new(bus: Bus) -> Self {
+impl<'a> CPU<'a> {
+    pub fn new(bus: Bus<'a>) -> CPU<'a> {
         CPU {
             register_a: 0,
             register_x: 0,
@@ -96,6 +100,7 @@ impl CPU {
             program_counter: 0,
             stack_ptr: STACK_RESET,
             bus,
+            cycles_ticked: 0,
         }
     }
 
@@ -108,6 +113,7 @@ impl CPU {
     }
 
     pub fn reset(&mut self) {
+        self.bus.reset();
         self.register_a = 0;
         self.register_x = 0;
         self.register_y = 0;
@@ -118,6 +124,28 @@ impl CPU {
         self.program_counter = self.mem_read_u16(0xFFFC);
     }
 
+    fn interrupt(&mut self, vector: u16) {
+        self.stack_push_u16(self.program_counter);
+        let mut flags = self.status;
+        flags.remove(Flags::BREAK);
+        flags.insert(Flags::UNUSED);
+        self.stack_push(flags.bits());
+        self.set_flag(Flags::INTERRUPT_DISABLE);
+
+        self.bus.tick(2);
+        self.program_counter = self.mem_read_u16(vector);
+    }
+
+    // read-modify-write instructions write the value they read back unchanged, then
+    // write the result on the next cycle. mappers that ignore back to back writes
+    // (mmc1) depend on seeing both
+    fn write_modified(&mut self, addr: u16, value: u8, result: u8) {
+        self.mem_write(addr, value);
+        self.bus.tick(1);
+        self.cycles_ticked += 1;
+        self.mem_write(addr, result);
+    }
+
     pub fn load(&mut self, program: Vec<u8>) {
         for i in 0..(program.len() as u16) {
             self.mem_write(0x0600 + i, program[i as usize]);
@@ -282,7 +310,7 @@ impl CPU {
 
             self.update_carry_flag(value);
             let result = value << 1;
-            self.mem_write(addr, result);
+            self.write_modified(addr, value, result);
             self.update_zero_and_negative_flags(result);
             result
         }
@@ -334,7 +362,7 @@ impl CPU {
         let value = self.mem_read(addr);
 
         let result = value.wrapping_sub(1);
-        self.mem_write(addr, result);
+        self.write_modified(addr, value, result);
         self.update_zero_and_negative_flags(result);
     }
 
@@ -361,7 +389,7 @@ impl CPU {
         let value = self.mem_read(addr);
 
         let result = value.wrapping_add(1);
-        self.mem_write(addr, result);
+        self.write_modified(addr, value, result);
         self.update_zero_and_negative_flags(result);
         result
     }
@@ -413,7 +441,8 @@ impl CPU {
 
     fn lsr(&mut self, mode: &AddressingMode) -> u8 {
         let addr = self.get_operand_address(mode);
-        let mut data = self.mem_read(addr);
+        let value = self.mem_read(addr);
+        let mut data = value;
 
         if data & 1 == 1 {
             self.set_flag(Flags::CARRY);
@@ -421,7 +450,7 @@ impl CPU {
             self.clear_flag(Flags::CARRY);
         }
         data = data >> 1;
-        self.mem_write(addr, data);
+        self.write_modified(addr, value, data);
         self.update_zero_and_negative_flags(data);
         data
     }
@@ -459,7 +488,8 @@ impl CPU {
 
     fn rol(&mut self, mode: &AddressingMode) -> u8 {
         let addr = self.get_operand_address(mode);
-        let mut value = self.mem_read(addr);
+        let data = self.mem_read(addr);
+        let mut value = data;
         let old_carry = self.status.contains(Flags::CARRY);
         if value >> 7 == 1 {
             self.set_flag(Flags::CARRY);
@@ -470,7 +500,7 @@ impl CPU {
         if old_carry {
             value = value | 1;
         }
-        self.mem_write(addr, value);
+        self.write_modified(addr, data, value);
         self.update_zero_and_negative_flags(value);
         value
     }
@@ -493,7 +523,8 @@ impl CPU {
 
     fn ror(&mut self, mode: &AddressingMode) -> u8 {
         let addr = self.get_operand_address(mode);
-        let mut value = self.mem_read(addr);
+        let data = self.mem_read(addr);
+        let mut value = data;
         let old_carry = self.status.contains(Flags::CARRY);
         if value & 1 == 1 {
             self.set_flag(Flags::CARRY);
@@ -504,7 +535,7 @@ impl CPU {
         if old_carry {
             value = value | (1 << 7);
         }
-        self.mem_write(addr, value);
+        self.write_modified(addr, data, value);
         self.update_zero_and_negative_flags(value);
         value
     }
@@ -523,9 +554,9 @@ impl CPU {
 
     pub fn run_with_callback<F>(&mut self, mut callback: F)
     where
-        F: FnMut(&mut CPU),
+        F: FnMut(&mut CPU<'a>),
     {
-        let mut file = OpenOptions::new()
+        let file = OpenOptions::new()
             .write(true)
             .append(true)
             .create(true)
@@ -533,19 +564,50 @@ impl CPU {
             .unwrap();
         file.set_len(0).unwrap();
 
+        self.run_loop(
+            |cpu| {
+                callback(cpu);
+                false
+            },
+            Some(file),
+        );
+    }
+
+    // runs until `done` returns true after an instruction (or a BRK is hit),
+    // without writing the cpu.log trace. used for headless runs
+    pub fn run_until<F>(&mut self, done: F)
+    where
+        F: FnMut(&mut CPU<'a>) -> bool,
+    {
+        self.run_loop(done, None);
+    }
+
+    fn run_loop<F>(&mut self, mut done: F, mut trace: Option<File>)
+    where
+        F: FnMut(&mut CPU<'a>) -> bool,
+    {
         loop {
+            if self.bus.poll_nmi_status().is_some() {
+                self.interrupt(NMI_VECTOR);
+            } else if self.bus.poll_irq_status() && !self.status.contains(Flags::INTERRUPT_DISABLE)
+            {
+                self.interrupt(IRQ_VECTOR);
+            }
+
             let opcode = self.mem_read(self.program_counter);
 
-            if let Err(e) = writeln!(
-                file,
-                "{opcode:#04X}    A:{:#04X} X:{:#04X} Y:{:#04X} P:{:#04X} SP:{:#04X}",
-                self.register_a,
-                self.register_x,
-                self.register_y,
-                self.status.bits(),
-                self.stack_ptr
-            ) {
-                eprintln!("Couldn't write to file: {e}");
+            if let Some(file) = trace.as_mut() {
+                if let Err(e) = writeln!(
+                    file,
+                    "{opcode:#04X}    A:{:#04X} X:{:#04X} Y:{:#04X} P:{:#04X} SP:{:#04X}",
+                    self.register_a,
+                    self.register_x,
+                    self.register_y,
+                    self.status.bits(),
+                    self.stack_ptr
+                ) {
+                    eprintln!("Couldn't write to file: {e}");
+                }
             }
             self.program_counter += 1;
             let program_counter_state = self.program_counter;
@@ -909,7 +971,7 @@ impl CPU {
                     let addr = self.get_operand_address(&OPCODE_MAP[&opcode].mode);
                     let data = self.mem_read(addr);
                     let result = data.wrapping_sub(1);
-                    self.mem_write(addr, result);
+                    self.write_modified(addr, data, result);
                     if result <= self.register_a {
                         self.status.insert(Flags::CARRY);
                     }
@@ -1036,10 +1098,16 @@ impl CPU {
                     self.mem_write(mem_addr, data);
                 }
             }
+            self.bus
+                .tick(OPCODE_MAP[&opcode].cycles - self.cycles_ticked);
+            self.cycles_ticked = 0;
+
             if program_counter_state == self.program_counter {
                 self.program_counter += (OPCODE_MAP[&opcode].length - 1) as u16;
             }
-            callback(self);
+            if done(self) {
+                return;
+            }
         }
     }
 }
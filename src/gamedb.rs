@@ -0,0 +1,79 @@
+// a game database compiled into the emulator, keyed by the checksums of a rom's prg
+// and chr data. the header in front of that data was typed in by whoever made the
+// dump, and plenty of iNES 1.0 files get the mapper, mirroring or battery wrong, while
+// the data itself identifies the cartridge. the fields follow the NES 2.0 database
+// (nes20db.xml), and build.rs turns data/nes20db.xml into the table included below.
+// https://www.nesdev.org/wiki/NES_2.0#Database
+
+use crate::cartridge::{Mirroring, Timing};
+use crate::checksum::to_hex;
+
+// what the database knows about one dump
+#[derive(Debug, PartialEq)]
+pub struct Game {
+    pub title: &'static str,
+    // of the prg rom followed by the chr rom, without the header or trainer
+    pub crc32: u32,
+    pub sha1: &'static str,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: Timing,
+}
+
+// looks a rom up by its checksums. crc-32 finds the entry and sha-1 confirms it,
+// so a crc collision can't pass one game off as another
+pub fn find(crc32: u32, sha1: &[u8; 20]) -> Option<&'static Game> {
+    let sha1 = to_hex(sha1);
+    let first = GAMES.partition_point(|game| game.crc32 < crc32);
+    GAMES[first..]
+        .iter()
+        .take_while(|game| game.crc32 == crc32)
+        .find(|game| game.sha1 == sha1)
+}
+
+// sorted by crc32
+include!(concat!(env!("OUT_DIR"), "/games.rs"));
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::checksum::sha1;
+
+    #[test]
+    fn test_table_is_sorted() {
+        assert!(GAMES.windows(2).all(|pair| pair[0].crc32 <= pair[1].crc32));
+    }
+
+    #[test]
+    fn test_table_matches_database() {
+        let nestest = &GAMES[0];
+        assert_eq!(nestest.title, "nestest");
+        assert_eq!(nestest.crc32, 0x158b_0388);
+        assert_eq!(nestest.sha1, "4131307f0f69f2a5c54b7d438328c5b2a5ed0820");
+        assert_eq!(nestest.mirroring, Mirroring::HORIZONTAL);
+
+        let snake = &GAMES[1];
+        assert_eq!(snake.title, "Snake");
+        assert_eq!(snake.mirroring, Mirroring::VERTICAL);
+        assert_eq!(snake.chr_ram_size, 0x2000);
+        assert_eq!(snake.timing, Timing::NTSC);
+    }
+
+    #[test]
+    fn test_find_needs_both_checksums() {
+        let game = &GAMES[0];
+        let mut digest = [0; 20];
+        for (i, byte) in digest.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&game.sha1[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        assert_eq!(find(game.crc32, &digest), Some(game));
+        assert_eq!(find(game.crc32, &sha1(b"something else")), None);
+        assert_eq!(find(game.crc32 ^ 1, &digest), None);
+    }
+}
@@ -0,0 +1,68 @@
+// standard controller, read one button at a time through a shift register at $4016/$4017
+// https://www.nesdev.org/wiki/Standard_controller
+
+use bitflags::bitflags;
+
+bitflags! {
+    pub struct JoypadButton: u8 {
+        const RIGHT = 0b1000_0000;
+        const LEFT = 0b0100_0000;
+        const DOWN = 0b0010_0000;
+        const UP = 0b0001_0000;
+        const START = 0b0000_1000;
+        const SELECT = 0b0000_0100;
+        const BUTTON_B = 0b0000_0010;
+        const BUTTON_A = 0b0000_0001;
+    }
+}
+
+pub struct Joypad {
+    strobe: bool,
+    button_index: u8,
+    button_status: JoypadButton,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            strobe: false,
+            button_index: 0,
+            button_status: JoypadButton::empty(),
+        }
+    }
+
+    //while strobe is high the shift register keeps reloading, so reads return button a
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.button_index = 0;
+        }
+    }
+
+    //buttons come out in the order a, b, select, start, up, down, left, right,
+    //then 1s once all eight have been read
+    pub fn read(&mut self) -> u8 {
+        if self.button_index > 7 {
+            return 1;
+        }
+        let response = (self.button_status.bits() >> self.button_index) & 1;
+        if !self.strobe {
+            self.button_index += 1;
+        }
+        response
+    }
+
+    pub fn set_button_pressed_status(&mut self, button: JoypadButton, pressed: bool) {
+        self.button_status.set(button, pressed);
+    }
+
+    pub fn buttons(&self) -> JoypadButton {
+        self.button_status
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -4,6 +4,13 @@ extern crate bitflags;
 
 pub mod bus;
 pub mod cartridge;
+pub mod checksum;
 pub mod cpu;
+pub mod gamedb;
+pub mod joypad;
+pub mod mapper;
 pub mod opcodes;
 pub mod ppu;
+pub mod region;
+pub mod render;
+pub mod save;
@@ -1,12 +1,52 @@
 use nes_rust::bus::Bus;
 use nes_rust::cartridge::Rom;
 use nes_rust::cpu::{Mem, CPU};
+use nes_rust::joypad::JoypadButton;
+use nes_rust::ppu::{RenderMode, PPU};
+use nes_rust::region::Region;
+use nes_rust::render::frame::Frame;
+use nes_rust::render::palette::SystemPalette;
+use nes_rust::render::png;
+use nes_rust::render::viewer::{self, Image};
+use nes_rust::save::{self, SaveFile};
 use rand::Rng;
-use sdl2::event::Event;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
 use sdl2::pixels::PixelFormatEnum;
-use sdl2::EventPump;
+use sdl2::render::WindowCanvas;
+use sdl2::{EventPump, VideoSubsystem};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+const NES_SCALE: u32 = 3;
+// battery backed ram is written out this often (about 5 seconds), as well as on exit
+const AUTOSAVE_FRAMES: u64 = 300;
+
+// command line settings that change how a rom runs
+#[derive(Default)]
+struct Options {
+    render_mode: Option<RenderMode>,
+    palette: Option<SystemPalette>,
+    // takes precedence over the timing in the rom's header
+    region: Option<Region>,
+}
+
+impl Options {
+    fn apply(self, cpu: &mut CPU) {
+        if let Some(mode) = self.render_mode {
+            cpu.bus.ppu_mut().set_render_mode(mode);
+        }
+        if let Some(region) = self.region {
+            cpu.bus.set_region(region);
+        }
+        if let Some(palette) = self.palette {
+            cpu.bus.ppu_mut().system_palette = palette;
+        }
+    }
+}
 
 fn color(byte: u8) -> Color {
     match byte {
@@ -76,7 +116,363 @@ fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
     }
 }
 
+#[derive(Clone, Copy)]
+enum DebugView {
+    PatternTables,
+    Nametables,
+    Oam,
+    Palette,
+}
+
+// a separate window showing one of the ppu viewers, refreshed every frame while open
+struct DebugWindow {
+    view: DebugView,
+    canvas: WindowCanvas,
+    visible: bool,
+}
+
+impl DebugWindow {
+    fn new(video_subsystem: &VideoSubsystem, view: DebugView) -> Self {
+        let (title, width, height, scale) = match view {
+            DebugView::PatternTables => ("Pattern tables", 256, 128, 2),
+            DebugView::Nametables => ("Nametables", 512, 480, 1),
+            DebugView::Oam => ("OAM", 128, 192, 2),
+            DebugView::Palette => ("Palette", 256, 32, 2),
+        };
+        let window = video_subsystem
+            .window(title, width * scale, height * scale)
+            .hidden()
+            .build()
+            .unwrap();
+        let mut canvas = window.into_canvas().build().unwrap();
+        canvas.set_scale(scale as f32, scale as f32).unwrap();
+        DebugWindow {
+            view,
+            canvas,
+            visible: false,
+        }
+    }
+
+    fn id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    fn set_visible(&mut self, visible: bool) {
+        if visible {
+            self.canvas.window_mut().show();
+        } else {
+            self.canvas.window_mut().hide();
+        }
+        self.visible = visible;
+    }
+
+    fn draw(&mut self, ppu: &PPU, pattern_palette: u8) {
+        if !self.visible {
+            return;
+        }
+        let image: Image = match self.view {
+            DebugView::PatternTables => viewer::render_pattern_tables(ppu, pattern_palette),
+            DebugView::Nametables => viewer::render_nametables(ppu),
+            DebugView::Oam => viewer::render_oam(ppu),
+            DebugView::Palette => viewer::render_palette(ppu),
+        };
+        let creator = self.canvas.texture_creator();
+        let mut texture = creator
+            .create_texture_target(
+                PixelFormatEnum::RGB24,
+                image.width as u32,
+                image.height as u32,
+            )
+            .unwrap();
+        texture.update(None, &image.data, image.width * 3).unwrap();
+        self.canvas.copy(&texture, None, None).unwrap();
+        self.canvas.present();
+    }
+}
+
+// arrows are the d-pad, a/s are a/b, return is start and space is select.
+// F1-F4 toggle the pattern table, nametable, OAM and palette windows,
+// P cycles the palette used for the pattern tables, F12 saves a screenshot.
+// games with battery backed ram are saved to a .sav file next to the rom
+fn run_nes(path: &str, options: Options) {
+    let rom = match load_rom(path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window(
+            rom.title().unwrap_or(path),
+            Frame::WIDTH as u32 * NES_SCALE,
+            Frame::HEIGHT as u32 * NES_SCALE,
+        )
+        .position_centered()
+        .build()
+        .unwrap();
+
+    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    canvas
+        .set_scale(NES_SCALE as f32, NES_SCALE as f32)
+        .unwrap();
+
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_target(
+            PixelFormatEnum::RGB24,
+            Frame::WIDTH as u32,
+            Frame::HEIGHT as u32,
+        )
+        .unwrap();
+
+    let mut debug_windows: Vec<DebugWindow> = [
+        DebugView::PatternTables,
+        DebugView::Nametables,
+        DebugView::Oam,
+        DebugView::Palette,
+    ]
+    .iter()
+    .map(|view| DebugWindow::new(&video_subsystem, *view))
+    .collect();
+    let mut pattern_palette = 0;
+    let mut frame_count: u64 = 0;
+
+    let mut key_map = HashMap::new();
+    key_map.insert(Keycode::Down, JoypadButton::DOWN);
+    key_map.insert(Keycode::Up, JoypadButton::UP);
+    key_map.insert(Keycode::Right, JoypadButton::RIGHT);
+    key_map.insert(Keycode::Left, JoypadButton::LEFT);
+    key_map.insert(Keycode::Space, JoypadButton::SELECT);
+    key_map.insert(Keycode::Return, JoypadButton::START);
+    key_map.insert(Keycode::A, JoypadButton::BUTTON_A);
+    key_map.insert(Keycode::S, JoypadButton::BUTTON_B);
+
+    //the event loop runs inside the bus callback, which can't reach the cartridge's
+    //ram, so quitting is left to the cpu loop where the save can be written first
+    let quit = Rc::new(Cell::new(false));
+    let quit_requested = quit.clone();
+
+    let save_path = save::path_for_rom(Path::new(path), rom.crc32);
+    let bus = Bus::new_with_callback(rom, move |ppu, joypad| {
+        frame_count += 1;
+        texture
+            .update(None, &ppu.frame.data, Frame::WIDTH * 3)
+            .unwrap();
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => quit_requested.set(true),
+                Event::Window {
+                    window_id,
+                    win_event: WindowEvent::Close,
+                    ..
+                } => {
+                    if let Some(window) = debug_windows.iter_mut().find(|w| w.id() == window_id) {
+                        window.set_visible(false);
+                    } else {
+                        quit_requested.set(true);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(button) = key_map.get(&keycode) {
+                        joypad.set_button_pressed_status(*button, true);
+                    }
+                    let toggled = match keycode {
+                        Keycode::F1 => Some(0),
+                        Keycode::F2 => Some(1),
+                        Keycode::F3 => Some(2),
+                        Keycode::F4 => Some(3),
+                        Keycode::P => {
+                            pattern_palette = (pattern_palette + 1) % 8;
+                            None
+                        }
+                        Keycode::F12 => {
+                            let path = format!("screenshot-{frame_count}.png");
+                            match png::save_frame(&ppu.frame, &path) {
+                                Ok(()) => println!("Saved {path}"),
+                                Err(e) => eprintln!("{e}"),
+                            }
+                            None
+                        }
+                        _ => None,
+                    };
+                    if let Some(idx) = toggled {
+                        let window = &mut debug_windows[idx];
+                        window.set_visible(!window.visible);
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(button) = key_map.get(&keycode) {
+                        joypad.set_button_pressed_status(*button, false);
+                    }
+                }
+                _ => { /* do nothing */ }
+            }
+        }
+
+        for window in debug_windows.iter_mut() {
+            window.draw(ppu, pattern_palette);
+        }
+    });
+
+    let mut cpu = CPU::new(bus);
+    options.apply(&mut cpu);
+    let mut save_file = SaveFile::new(save_path);
+    if cpu.bus.battery_ram().is_some() {
+        match save_file.load() {
+            Ok(Some(data)) => cpu.bus.load_battery_ram(&data),
+            Ok(None) => {}
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+    cpu.reset();
+
+    //runs without the cpu.log trace, which would grow by megabytes a second
+    let mut next_save = AUTOSAVE_FRAMES;
+    cpu.run_until(move |cpu| {
+        let quitting = quit.get();
+        if quitting || cpu.bus.frames() >= next_save {
+            next_save = cpu.bus.frames() + AUTOSAVE_FRAMES;
+            if let Some(ram) = cpu.bus.battery_ram() {
+                if let Err(e) = save_file.save(&ram) {
+                    eprintln!("{e}");
+                }
+            }
+        }
+        quitting
+    });
+}
+
+// reads and checks a rom file, with errors worded for the user
+fn load_rom(path: &str) -> Result<Rom, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Couldn't read rom {path}: {e}"))?;
+    let rom = Rom::new(&bytes).map_err(|e| format!("Couldn't load {path}: {e}"))?;
+    if rom.header.disk_dude {
+        eprintln!("{path}: ignoring the \"DiskDude!\" junk in its header");
+    }
+    if let Some(title) = rom.title() {
+        eprintln!("{path}: {title}");
+    }
+    for correction in &rom.corrections {
+        eprintln!("{path}: header is wrong, {correction}");
+    }
+    Ok(rom)
+}
+
+// runs the rom without opening any windows and writes the frame completed at
+// `frame` to `out` as a png
+fn screenshot_at_frame(path: &str, frame: u64, out: &str, options: Options) -> Result<(), String> {
+    let rom = load_rom(path)?;
+
+    let mut cpu = CPU::new(Bus::new(rom));
+    options.apply(&mut cpu);
+    cpu.reset();
+    cpu.run_until(|cpu| cpu.bus.frames() >= frame);
+    if cpu.bus.frames() < frame {
+        return Err(format!(
+            "Program stopped after {} frames, before frame {frame}",
+            cpu.bus.frames()
+        ));
+    }
+    png::save_frame(&cpu.bus.ppu().frame, out)
+}
+
+// the value following an option, exiting with a usage message when it's missing
+fn option_value(args: &mut impl Iterator<Item = String>, usage: &str) -> String {
+    args.next().unwrap_or_else(|| {
+        eprintln!("usage: {usage}");
+        std::process::exit(2);
+    })
+}
+
+// usage: nes-rust [rom.nes] [--screenshot-at-frame N out.png] [--render-mode scanline|cycle]
+//                 [--palette file.pal] [--region ntsc|pal|dendy]
+// without a rom the bundled snake game runs, drawn straight from its ram
 fn main() {
+    let mut rom_path = None;
+    let mut screenshot = None;
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--screenshot-at-frame" {
+            let (Some(frame), Some(out)) = (args.next(), args.next()) else {
+                eprintln!("usage: --screenshot-at-frame N out.png");
+                std::process::exit(2);
+            };
+            let Ok(frame) = frame.parse::<u64>() else {
+                eprintln!("Invalid frame number: {frame}");
+                std::process::exit(2);
+            };
+            screenshot = Some((frame, out));
+        } else if arg == "--render-mode" {
+            let usage = "--render-mode scanline|cycle";
+            options.render_mode = match option_value(&mut args, usage).as_str() {
+                "scanline" => Some(RenderMode::Scanline),
+                "cycle" => Some(RenderMode::Cycle),
+                mode => {
+                    eprintln!("Unknown render mode {mode}, usage: {usage}");
+                    std::process::exit(2);
+                }
+            };
+        } else if arg == "--region" {
+            let usage = "--region ntsc|pal|dendy";
+            options.region = match option_value(&mut args, usage).as_str() {
+                "ntsc" => Some(Region::NTSC),
+                "pal" => Some(Region::PAL),
+                "dendy" => Some(Region::DENDY),
+                region => {
+                    eprintln!("Unknown region {region}, usage: {usage}");
+                    std::process::exit(2);
+                }
+            };
+        } else if arg == "--palette" {
+            let path = option_value(&mut args, "--palette file.pal");
+            match SystemPalette::load(&path) {
+                Ok(palette) => options.palette = Some(palette),
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            rom_path = Some(arg);
+        }
+    }
+
+    match (rom_path, screenshot) {
+        (Some(path), Some((frame, out))) => {
+            if let Err(e) = screenshot_at_frame(&path, frame, &out, options) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        (None, Some(_)) => {
+            eprintln!("--screenshot-at-frame needs a rom to run");
+            std::process::exit(2);
+        }
+        (Some(path), None) => run_nes(&path, options),
+        (None, None) => run_snake(),
+    }
+}
+
+fn run_snake() {
     // init sdl2
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
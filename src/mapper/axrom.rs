@@ -0,0 +1,116 @@
+// mapper 7: 32KB prg banks, and a single-screen nametable picked by bit 4 of the
+// same register. the common ANROM/AOROM boards keep the rom off the bus during
+// writes, but AMROM (NES 2.0 submapper 2) doesn't and has bus conflicts
+
+use super::{new_prg_ram, Mapper};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+pub struct Axrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    bus_conflicts: bool,
+    bank: u8,
+}
+
+impl Axrom {
+    pub fn new(rom: Rom) -> Self {
+        let prg_ram = new_prg_ram(&rom);
+        Axrom {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr: rom.chr_rom,
+            chr_ram: rom.chr_ram,
+            bus_conflicts: rom.submapper == 2,
+            bank: 0,
+        }
+    }
+
+    fn read_prg_rom(&self, addr: u16) -> u8 {
+        let bank = (self.bank & 0b111) as usize;
+        let idx = bank * PRG_BANK_SIZE + (addr - 0x8000) as usize;
+        self.prg_rom[idx % self.prg_rom.len()]
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.read_prg_rom(addr),
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize] = data,
+            0x8000..=0xffff if self.bus_conflicts => self.bank = data & self.read_prg_rom(addr),
+            0x8000..=0xffff => self.bank = data,
+            _ => {}
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[addr as usize % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let idx = addr as usize % self.chr.len();
+            self.chr[idx] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.bank & 0b1_0000 != 0 {
+            Mirroring::SINGLE_SCREEN_UPPER
+        } else {
+            Mirroring::SINGLE_SCREEN_LOWER
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::test_rom;
+
+    #[test]
+    fn test_prg_bank_and_nametable_select() {
+        let mut axrom = Axrom::new(test_rom(7, 8, 0));
+        assert_eq!(axrom.mirroring(), Mirroring::SINGLE_SCREEN_LOWER);
+
+        //no bus conflict, page 0 at $8000 would otherwise clear every bit
+        axrom.cpu_write(0x8000, 0b1_0011);
+        assert_eq!(axrom.cpu_read(0x8000), 3 * 32);
+        assert_eq!(axrom.cpu_read(0xfc00), 3 * 32 + 31);
+        assert_eq!(axrom.mirroring(), Mirroring::SINGLE_SCREEN_UPPER);
+    }
+
+    #[test]
+    fn test_amrom_bus_conflict() {
+        let mut rom = test_rom(7, 8, 0);
+        rom.submapper = 2;
+        let mut axrom = Axrom::new(rom);
+        //page 15 (0b1111) at $bc00 lets the bank number through but clears bit 4
+        axrom.cpu_write(0xbc00, 0b1_0011);
+        assert_eq!(axrom.cpu_read(0x8000), 3 * 32);
+        assert_eq!(axrom.mirroring(), Mirroring::SINGLE_SCREEN_LOWER);
+
+        //bank 3 has page 3 * 32 + 1 = 0b110_0001 at $8400, clearing bit 1
+        axrom.cpu_write(0x8400, 0b11);
+        assert_eq!(axrom.cpu_read(0x8000), 32);
+    }
+}
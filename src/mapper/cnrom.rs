@@ -0,0 +1,88 @@
+// mapper 3: fixed prg like nrom, with the whole 8KB of chr switched by writes to
+// $8000-$FFFF. like uxrom the writes are subject to bus conflicts
+
+use super::{new_prg_ram, Mapper};
+use crate::cartridge::{Mirroring, Rom};
+
+const CHR_BANK_SIZE: usize = 0x2000;
+
+pub struct Cnrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    mirroring: Mirroring,
+    chr_bank: u8,
+}
+
+impl Cnrom {
+    pub fn new(rom: Rom) -> Self {
+        let prg_ram = new_prg_ram(&rom);
+        Cnrom {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr: rom.chr_rom,
+            mirroring: rom.screen_mirroring,
+            chr_bank: 0,
+        }
+    }
+
+    fn read_prg_rom(&self, addr: u16) -> u8 {
+        self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()]
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.read_prg_rom(addr),
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize] = data,
+            0x8000..=0xffff => self.chr_bank = data & self.read_prg_rom(addr),
+            _ => {}
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        let idx = self.chr_bank as usize * CHR_BANK_SIZE + addr as usize;
+        self.chr[idx % self.chr.len()]
+    }
+
+    //cnrom boards only come with chr rom
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::test_rom;
+
+    #[test]
+    fn test_chr_bank_switch_with_bus_conflict() {
+        let mut cnrom = Cnrom::new(test_rom(3, 2, 4));
+        //$bc00 holds page 15 (0b1111), so the whole bank number gets through
+        cnrom.cpu_write(0xbc00, 2);
+        assert_eq!(cnrom.ppu_read(0x0400), 2 * 8 + 1);
+
+        //$8400 holds page 1, clearing bit 1
+        cnrom.cpu_write(0x8400, 3);
+        assert_eq!(cnrom.ppu_read(0x0400), 8 + 1);
+    }
+}
@@ -0,0 +1,110 @@
+// four-screen boards carry 2KB of nametable ram of their own, which together with the
+// console's 2KB gives every nametable its own memory. the header can ask for it on any
+// mapper, so from_rom wraps whichever board the rom uses and answers for the $2800 and
+// $2C00 nametables here, passing everything else through.
+// https://www.nesdev.org/wiki/Mirroring#4-Screen
+
+use super::{Mapper, Nametable, PatternFetch};
+use crate::cartridge::Mirroring;
+
+pub struct FourScreen<M: Mapper> {
+    mapper: M,
+    vram: [u8; 0x800],
+}
+
+impl<M: Mapper> FourScreen<M> {
+    pub fn new(mapper: M) -> Self {
+        FourScreen {
+            mapper,
+            vram: [0; 0x800],
+        }
+    }
+
+    //boards that switch mirroring themselves can leave four-screen mode
+    fn active(&self) -> bool {
+        self.mapper.mirroring() == Mirroring::FOUR_SCREEN
+    }
+}
+
+impl<M: Mapper> Mapper for FourScreen<M> {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.mapper.cpu_read(addr)
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        self.mapper.cpu_write(addr, data)
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        self.mapper.prg_ram()
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        self.mapper.prg_ram_mut()
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.mapper.ppu_read(addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.mapper.ppu_write(addr, data)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mapper.mirroring()
+    }
+
+    fn nametable(&self, table: u16) -> Nametable {
+        self.mapper.nametable(table)
+    }
+
+    //only $2800-$2FFF are placed on the cartridge in four-screen mode
+    fn read_nametable(&self, addr: u16) -> u8 {
+        if self.active() {
+            self.vram[addr as usize & 0x7ff]
+        } else {
+            self.mapper.read_nametable(addr)
+        }
+    }
+
+    fn write_nametable(&mut self, addr: u16, data: u8) {
+        if self.active() {
+            self.vram[addr as usize & 0x7ff] = data;
+        } else {
+            self.mapper.write_nametable(addr, data)
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.mapper.irq()
+    }
+
+    fn ppu_address(&mut self, addr: u16) {
+        self.mapper.ppu_address(addr)
+    }
+
+    fn pattern_fetch(&mut self, addr: u16, kind: PatternFetch) -> u8 {
+        self.mapper.pattern_fetch(addr, kind)
+    }
+
+    fn nametable_fetch(&mut self, addr: u16, value: u8) -> u8 {
+        self.mapper.nametable_fetch(addr, value)
+    }
+
+    fn scanline(&mut self) {
+        self.mapper.scanline()
+    }
+
+    fn ppu_line(&mut self, scanline: u16, rendering: bool) {
+        self.mapper.ppu_line(scanline, rendering)
+    }
+
+    fn ppu_register_write(&mut self, addr: u16, data: u8) {
+        self.mapper.ppu_register_write(addr, data)
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.mapper.cpu_cycle()
+    }
+}
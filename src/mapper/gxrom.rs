@@ -0,0 +1,98 @@
+// mapper 66: one register at $8000-$FFFF selecting a 32KB prg bank (bits 4-5) and an
+// 8KB chr bank (bits 0-1), with bus conflicts
+
+use super::{new_prg_ram, Mapper};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+pub struct Gxrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    mirroring: Mirroring,
+    bank: u8,
+}
+
+impl Gxrom {
+    pub fn new(rom: Rom) -> Self {
+        let prg_ram = new_prg_ram(&rom);
+        Gxrom {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr: rom.chr_rom,
+            chr_ram: rom.chr_ram,
+            mirroring: rom.screen_mirroring,
+            bank: 0,
+        }
+    }
+
+    fn read_prg_rom(&self, addr: u16) -> u8 {
+        let bank = ((self.bank >> 4) & 0b11) as usize;
+        let idx = bank * PRG_BANK_SIZE + (addr - 0x8000) as usize;
+        self.prg_rom[idx % self.prg_rom.len()]
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let bank = (self.bank & 0b11) as usize;
+        (bank * CHR_BANK_SIZE + addr as usize) % self.chr.len()
+    }
+}
+
+impl Mapper for Gxrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.read_prg_rom(addr),
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize] = data,
+            0x8000..=0xffff => self.bank = data & self.read_prg_rom(addr),
+            _ => {}
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let idx = self.chr_addr(addr);
+            self.chr[idx] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::test_rom;
+
+    #[test]
+    fn test_prg_and_chr_banks_with_bus_conflict() {
+        let mut gxrom = Gxrom::new(test_rom(66, 8, 4));
+        //$fc00 holds page 31 (0b1_1111), letting the chr bits and prg bit 4 through
+        gxrom.cpu_write(0xfc00, 0b0011_0010);
+        assert_eq!(gxrom.cpu_read(0x8000), 32);
+        assert_eq!(gxrom.ppu_read(0x0000), 2 * 8);
+    }
+}
@@ -0,0 +1,436 @@
+// mapper 1: nintendo's mmc1. registers are loaded one bit at a time through a 5 bit
+// shift register; the fifth write picks the register from address bits 13-14.
+// https://www.nesdev.org/wiki/MMC1
+//
+// boards with 8KB of chr ram have no use for the chr bank registers' upper bits and
+// wire them elsewhere instead:
+//   SNROM  bit 4 disables prg ram
+//   SOROM  bit 3 selects one of two 8KB prg ram banks
+//   SUROM  bit 4 selects the 256KB half of a 512KB prg rom
+//   SXROM  as SUROM, plus bits 2-3 select one of four 8KB prg ram banks
+
+use super::{new_prg_ram, Mapper, PatternFetch};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+const PRG_RAM_BANK_SIZE: usize = 0x2000;
+// prg bank numbers are 4 bits, so one register reaches 256KB
+const PRG_OUTER_BANK_SIZE: usize = 0x40000;
+
+// shift register contents after a reset, the 1 marks when 5 bits have been loaded
+const SHIFT_RESET: u8 = 0b1_0000;
+
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+
+    shift: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+
+    //which chr register the ppu last fetched through, for boards using its upper bits
+    chr_a12: bool,
+    cycles: u64,
+    last_write_cycle: Option<u64>,
+}
+
+impl Mmc1 {
+    pub fn new(rom: Rom) -> Self {
+        let prg_ram = new_prg_ram(&rom);
+        Mmc1 {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr: rom.chr_rom,
+            chr_ram: rom.chr_ram,
+            shift: SHIFT_RESET,
+            //powers on in prg mode 3, so the last bank (with the vectors) is at $C000
+            control: 0b0_1100,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            chr_a12: false,
+            cycles: 0,
+            last_write_cycle: None,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0x9fff => self.control = data,
+            0xa000..=0xbfff => self.chr_bank_0 = data,
+            0xc000..=0xdfff => self.chr_bank_1 = data,
+            _ => self.prg_bank = data,
+        }
+    }
+
+    fn chr_8kb_mode(&self) -> bool {
+        self.control & 0b1_0000 == 0
+    }
+
+    //the chr register that drives the board specific lines
+    fn board_bits(&self) -> u8 {
+        if self.chr_8kb_mode() || !self.chr_a12 {
+            self.chr_bank_0
+        } else {
+            self.chr_bank_1
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        let disabled_by_board = self.chr_ram
+            && self.prg_rom.len() <= PRG_OUTER_BANK_SIZE
+            && self.board_bits() & 0b1_0000 != 0;
+        self.prg_bank & 0b1_0000 == 0 && !disabled_by_board
+    }
+
+    fn prg_ram_addr(&self, addr: u16) -> usize {
+        let bank = match self.prg_ram.len() / PRG_RAM_BANK_SIZE {
+            4 => (self.board_bits() >> 2) & 0b11,
+            2 => (self.board_bits() >> 3) & 0b1,
+            _ => 0,
+        } as usize;
+        bank * PRG_RAM_BANK_SIZE + (addr - 0x6000) as usize
+    }
+
+    fn prg_rom_addr(&self, addr: u16) -> usize {
+        let outer = if self.prg_rom.len() > PRG_OUTER_BANK_SIZE {
+            (self.board_bits() >> 4) as usize & 1
+        } else {
+            0
+        };
+        let last =
+            (PRG_OUTER_BANK_SIZE.min(self.prg_rom.len()) / PRG_BANK_SIZE).saturating_sub(1) as u8;
+        let bank = self.prg_bank & 0b1111;
+        let upper_half = addr >= 0xc000;
+        let bank = match (self.control >> 2) & 0b11 {
+            0 | 1 => (bank & 0b1110) | upper_half as u8,
+            2 if upper_half => bank,
+            2 => 0,
+            _ if upper_half => last,
+            _ => bank,
+        };
+        let idx = outer * PRG_OUTER_BANK_SIZE
+            + bank as usize * PRG_BANK_SIZE
+            + (addr as usize & (PRG_BANK_SIZE - 1));
+        idx % self.prg_rom.len()
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let upper_half = addr >= 0x1000;
+        let bank = if self.chr_8kb_mode() {
+            (self.chr_bank_0 & 0b1_1110) | upper_half as u8
+        } else if upper_half {
+            self.chr_bank_1
+        } else {
+            self.chr_bank_0
+        };
+        let idx = bank as usize * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1));
+        idx % self.chr.len()
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled() => self.prg_ram[self.prg_ram_addr(addr)],
+            0x8000..=0xffff => self.prg_rom[self.prg_rom_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled() => {
+                let idx = self.prg_ram_addr(addr);
+                self.prg_ram[idx] = data;
+            }
+            0x8000..=0xffff => {
+                //the mmc1 ignores a write on the cycle right after another, which is
+                //what read-modify-write instructions do with their dummy write
+                let consecutive = self
+                    .last_write_cycle
+                    .is_some_and(|last| self.cycles - last < 2);
+                self.last_write_cycle = Some(self.cycles);
+                if consecutive {
+                    return;
+                }
+
+                if data & 0b1000_0000 != 0 {
+                    self.shift = SHIFT_RESET;
+                    self.control |= 0b0_1100;
+                    return;
+                }
+                let full = self.shift & 1 == 1;
+                self.shift = (self.shift >> 1) | ((data & 1) << 4);
+                if full {
+                    self.write_register(addr, self.shift);
+                    self.shift = SHIFT_RESET;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let idx = self.chr_addr(addr);
+            self.chr[idx] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SINGLE_SCREEN_LOWER,
+            1 => Mirroring::SINGLE_SCREEN_UPPER,
+            2 => Mirroring::VERTICAL,
+            _ => Mirroring::HORIZONTAL,
+        }
+    }
+
+    fn ppu_address(&mut self, addr: u16) {
+        self.chr_a12 = addr & 0x1000 != 0;
+    }
+
+    //ppu_address only runs when rendering dot by dot, this keeps the board lines
+    //following the pattern fetches when whole scanlines are drawn too
+    fn pattern_fetch(&mut self, addr: u16, _kind: PatternFetch) -> u8 {
+        self.chr_a12 = addr & 0x1000 != 0;
+        self.ppu_read(addr)
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.cycles += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu::{Mem, CPU};
+    use crate::mapper::test_rom;
+    use crate::ppu::{RenderMode, PPU};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // a serial load of all 5 bits, spaced out like separate store instructions
+    fn load(mmc1: &mut Mmc1, addr: u16, value: u8) {
+        for bit in 0..5 {
+            for _ in 0..4 {
+                mmc1.cpu_cycle();
+            }
+            mmc1.cpu_write(addr, (value >> bit) & 1);
+        }
+    }
+
+    #[test]
+    fn test_power_on_fixes_last_bank_at_c000() {
+        let mut mmc1 = Mmc1::new(test_rom(1, 8, 1));
+        assert_eq!(mmc1.cpu_read(0x8000), 0);
+        assert_eq!(mmc1.cpu_read(0xffff), 8 * 16 - 1);
+    }
+
+    #[test]
+    fn test_prg_bank_modes() {
+        let mut mmc1 = Mmc1::new(test_rom(1, 8, 1));
+        load(&mut mmc1, 0xe000, 3);
+        assert_eq!(mmc1.cpu_read(0x8000), 3 * 16);
+        assert_eq!(mmc1.cpu_read(0xc000), 7 * 16);
+
+        //mode 2 fixes the first bank at $8000 and switches $C000
+        load(&mut mmc1, 0x8000, 0b0_1000);
+        assert_eq!(mmc1.cpu_read(0x8000), 0);
+        assert_eq!(mmc1.cpu_read(0xc000), 3 * 16);
+
+        //32KB mode ignores the low bit of the bank number
+        load(&mut mmc1, 0x8000, 0b0_0000);
+        assert_eq!(mmc1.cpu_read(0x8000), 2 * 16);
+        assert_eq!(mmc1.cpu_read(0xc000), 3 * 16);
+    }
+
+    #[test]
+    fn test_chr_bank_modes() {
+        let mut mmc1 = Mmc1::new(test_rom(1, 2, 4));
+        load(&mut mmc1, 0xa000, 3);
+        assert_eq!(mmc1.ppu_read(0x0000), 2 * 4);
+        assert_eq!(mmc1.ppu_read(0x1000), 3 * 4);
+
+        load(&mut mmc1, 0x8000, 0b1_1100);
+        load(&mut mmc1, 0xc000, 5);
+        assert_eq!(mmc1.ppu_read(0x0000), 3 * 4);
+        assert_eq!(mmc1.ppu_read(0x1000), 5 * 4);
+    }
+
+    #[test]
+    fn test_mirroring_from_control() {
+        let mut mmc1 = Mmc1::new(test_rom(1, 2, 1));
+        for (bits, mirroring) in [
+            (0, Mirroring::SINGLE_SCREEN_LOWER),
+            (1, Mirroring::SINGLE_SCREEN_UPPER),
+            (2, Mirroring::VERTICAL),
+            (3, Mirroring::HORIZONTAL),
+        ] {
+            load(&mut mmc1, 0x8000, 0b0_1100 | bits);
+            assert_eq!(mmc1.mirroring(), mirroring);
+        }
+    }
+
+    #[test]
+    fn test_reset_bit_clears_shift_register() {
+        let mut mmc1 = Mmc1::new(test_rom(1, 8, 1));
+        load(&mut mmc1, 0x8000, 0b0_0000);
+        for _ in 0..4 {
+            mmc1.cpu_cycle();
+        }
+        mmc1.cpu_write(0xe000, 1);
+        for _ in 0..4 {
+            mmc1.cpu_cycle();
+        }
+        mmc1.cpu_write(0x8000, 0x80);
+        assert_eq!(mmc1.control & 0b0_1100, 0b0_1100);
+
+        //a full load after the reset lands, not offset by the stray bit
+        load(&mut mmc1, 0xe000, 2);
+        assert_eq!(mmc1.cpu_read(0x8000), 2 * 16);
+    }
+
+    #[test]
+    fn test_consecutive_writes_are_ignored() {
+        let mut mmc1 = Mmc1::new(test_rom(1, 8, 1));
+        for bit in 0..5 {
+            for _ in 0..4 {
+                mmc1.cpu_cycle();
+            }
+            mmc1.cpu_write(0xe000, (1 >> bit) & 1);
+            //the dummy write of a read-modify-write instruction
+            mmc1.cpu_write(0xe000, 1);
+        }
+        assert_eq!(mmc1.prg_bank, 1);
+    }
+
+    #[test]
+    fn test_inc_on_ff_resets_shift_register() {
+        //the usual reset idiom, run by the cpu: INC $8000 on a $FF byte writes $FF back
+        //(resetting the shift register) and then $00, which is ignored
+        let mut rom = test_rom(1, 8, 1);
+        rom.prg_rom[0] = 0xff;
+        let mut cpu = CPU::new(Bus::new(rom));
+        let program = [
+            0xa9, 0x00, // LDA #$00
+            0x8d, 0x00, 0xe0, // STA $E000, three bits into the shift register
+            0x8d, 0x00, 0xe0, //
+            0x8d, 0x00, 0xe0, //
+            0xee, 0x00, 0x80, // INC $8000
+            0xa9, 0x01, // LDA #$01, then load prg bank 3
+            0x8d, 0x00, 0xe0, //
+            0x8d, 0x00, 0xe0, //
+            0xa9, 0x00, // LDA #$00
+            0x8d, 0x00, 0xe0, //
+            0x8d, 0x00, 0xe0, //
+            0x8d, 0x00, 0xe0, //
+            0x00, // BRK
+        ];
+        cpu.load(program.to_vec());
+        cpu.program_counter = 0x0600;
+        cpu.run_until(|_| false);
+        assert_eq!(cpu.mem_read(0x8000), 3 * 16);
+    }
+
+    #[test]
+    fn test_prg_ram_enable() {
+        let mut mmc1 = Mmc1::new(test_rom(1, 2, 1));
+        mmc1.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x42);
+
+        load(&mut mmc1, 0xe000, 0b1_0000);
+        assert_eq!(mmc1.cpu_read(0x6000), 0);
+        mmc1.cpu_write(0x6000, 0x11);
+        load(&mut mmc1, 0xe000, 0);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_snrom_chr_bit_4_disables_prg_ram() {
+        let mut mmc1 = Mmc1::new(test_rom(1, 16, 0));
+        mmc1.cpu_write(0x6000, 0x42);
+        load(&mut mmc1, 0xa000, 0b1_0000);
+        assert_eq!(mmc1.cpu_read(0x6000), 0);
+        load(&mut mmc1, 0xa000, 0);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_surom_chr_bit_4_selects_prg_outer_bank() {
+        let mut mmc1 = Mmc1::new(test_rom(1, 32, 0));
+        //page numbers wrap at 256KB, so check where the reads land instead
+        assert_eq!(mmc1.prg_rom_addr(0xc000) / PRG_BANK_SIZE, 15);
+        load(&mut mmc1, 0xa000, 0b1_0000);
+        assert_eq!(mmc1.prg_rom_addr(0x8000) / PRG_BANK_SIZE, 16);
+        assert_eq!(mmc1.prg_rom_addr(0xc000) / PRG_BANK_SIZE, 31);
+        //prg ram stays enabled on SUROM
+        mmc1.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_sxrom_chr_bits_2_3_select_prg_ram_bank() {
+        let mut rom = test_rom(1, 32, 0);
+        rom.prg_ram_size = 0x8000;
+        let mut mmc1 = Mmc1::new(rom);
+        for bank in 0..4u8 {
+            load(&mut mmc1, 0xa000, bank << 2);
+            mmc1.cpu_write(0x6000, bank + 1);
+        }
+        for bank in 0..4u8 {
+            load(&mut mmc1, 0xa000, bank << 2);
+            assert_eq!(mmc1.cpu_read(0x6000), bank + 1);
+        }
+        assert_eq!(mmc1.prg_ram[3 * PRG_RAM_BANK_SIZE], 4);
+    }
+
+    #[test]
+    fn test_prg_ram_bank_follows_ppu_fetches_in_both_render_modes() {
+        for mode in [RenderMode::Scanline, RenderMode::Cycle] {
+            let mut rom = test_rom(1, 32, 0);
+            rom.prg_ram_size = 0x8000;
+            let mmc1 = Rc::new(RefCell::new(Mmc1::new(rom)));
+            {
+                //4KB chr banks, with the register for $1000 picking prg ram bank 2
+                let mut board = mmc1.borrow_mut();
+                load(&mut board, 0x8000, 0b1_1100);
+                load(&mut board, 0xc000, 2 << 2);
+            }
+
+            //background and sprites both fetched from $1000
+            let mut ppu = PPU::new(mmc1.clone());
+            ppu.set_render_mode(mode);
+            ppu.write_to_ctrl(0b0001_1000);
+            ppu.write_to_mask(0b0001_1000);
+            while !ppu.tick(1) {}
+
+            mmc1.borrow_mut().cpu_write(0x6000, 0x42);
+            assert_eq!(
+                mmc1.borrow().prg_ram[2 * PRG_RAM_BANK_SIZE],
+                0x42,
+                "{mode:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,210 @@
+// mappers 9 and 10: nintendo's mmc2 (punch-out!!) and mmc4 (fire emblem). each 4KB
+// half of the pattern tables has two chr bank registers, and a latch picks between
+// them by watching for the ppu fetching tile $FD or $FE. the latch flips after the
+// fetch, so the tile that trips it is still drawn from the old bank.
+// https://www.nesdev.org/wiki/MMC2
+// https://www.nesdev.org/wiki/MMC4
+
+use super::{new_prg_ram, Mapper, PatternFetch};
+use crate::cartridge::{Mirroring, Rom};
+
+const CHR_BANK_SIZE: usize = 0x1000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Chip {
+    // 8KB prg bank at $8000 with the last three fixed, no prg ram
+    Mmc2,
+    // 16KB prg bank at $8000 with the last one fixed, and 8KB of prg ram
+    Mmc4,
+}
+
+pub struct Mmc2 {
+    chip: Chip,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+
+    prg_bank: u8,
+    //[$FD bank, $FE bank] for each pattern table
+    chr_banks: [[u8; 2]; 2],
+    //per pattern table, false after a $FD fetch and true after a $FE one
+    latches: [bool; 2],
+    horizontal_mirroring: bool,
+}
+
+impl Mmc2 {
+    pub fn new(rom: Rom, chip: Chip) -> Self {
+        let prg_ram = match chip {
+            Chip::Mmc2 => Vec::new(),
+            Chip::Mmc4 => new_prg_ram(&rom),
+        };
+        Mmc2 {
+            chip,
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr: rom.chr_rom,
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [true; 2],
+            horizontal_mirroring: rom.screen_mirroring == Mirroring::HORIZONTAL,
+        }
+    }
+
+    fn prg_rom_addr(&self, addr: u16) -> usize {
+        let bank_size = match self.chip {
+            Chip::Mmc2 => 0x2000,
+            Chip::Mmc4 => 0x4000,
+        };
+        let banks = self.prg_rom.len() / bank_size;
+        let slot = (addr - 0x8000) as usize / bank_size;
+        let bank = if slot == 0 {
+            self.prg_bank as usize
+        } else {
+            //the slots after the first hold the last banks of the rom, in order
+            (banks + slot).saturating_sub(0x8000 / bank_size)
+        };
+        (bank * bank_size + (addr as usize & (bank_size - 1))) % self.prg_rom.len()
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let table = (addr >> 12) as usize & 1;
+        let bank = self.chr_banks[table][self.latches[table] as usize];
+        (bank as usize * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))) % self.chr.len()
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.chip == Chip::Mmc4 => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.prg_rom[self.prg_rom_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff if self.chip == Chip::Mmc4 => {
+                self.prg_ram[(addr - 0x6000) as usize] = data;
+            }
+            0xa000..=0xafff => self.prg_bank = data & 0b1111,
+            0xb000..=0xbfff => self.chr_banks[0][0] = data & 0b1_1111,
+            0xc000..=0xcfff => self.chr_banks[0][1] = data & 0b1_1111,
+            0xd000..=0xdfff => self.chr_banks[1][0] = data & 0b1_1111,
+            0xe000..=0xefff => self.chr_banks[1][1] = data & 0b1_1111,
+            0xf000..=0xffff => self.horizontal_mirroring = data & 1 == 1,
+            _ => {}
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr)]
+    }
+
+    //mmc2 and mmc4 boards only come with chr rom
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        if self.horizontal_mirroring {
+            Mirroring::HORIZONTAL
+        } else {
+            Mirroring::VERTICAL
+        }
+    }
+
+    fn pattern_fetch(&mut self, addr: u16, _kind: PatternFetch) -> u8 {
+        let data = self.ppu_read(addr);
+        let table = (addr >> 12) as usize & 1;
+        //the mmc2 only watches the exact address for the first pattern table, any row
+        //of the tile's upper plane trips it everywhere else
+        let tile_addr = if self.chip == Chip::Mmc2 && table == 0 {
+            addr & 0x1fff
+        } else {
+            addr & 0x1ff8
+        };
+        match tile_addr & 0x0fff {
+            0x0fd8 => self.latches[table] = false,
+            0x0fe8 => self.latches[table] = true,
+            _ => {}
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::test_rom;
+
+    fn with_chr_banks(chip: Chip) -> Mmc2 {
+        let mut mmc2 = Mmc2::new(test_rom(9, 8, 8), chip);
+        mmc2.cpu_write(0xb000, 1);
+        mmc2.cpu_write(0xc000, 2);
+        mmc2.cpu_write(0xd000, 3);
+        mmc2.cpu_write(0xe000, 4);
+        mmc2
+    }
+
+    #[test]
+    fn test_mmc2_prg_banks() {
+        let mut mmc2 = Mmc2::new(test_rom(9, 8, 8), Chip::Mmc2);
+        mmc2.cpu_write(0xa000, 5);
+        assert_eq!(mmc2.cpu_read(0x8000), 5 * 8);
+        assert_eq!(mmc2.cpu_read(0xa000), 13 * 8);
+        assert_eq!(mmc2.cpu_read(0xc000), 14 * 8);
+        assert_eq!(mmc2.cpu_read(0xe000), 15 * 8);
+    }
+
+    #[test]
+    fn test_mmc4_prg_banks_and_ram() {
+        let mut mmc4 = Mmc2::new(test_rom(10, 8, 8), Chip::Mmc4);
+        mmc4.cpu_write(0xa000, 5);
+        assert_eq!(mmc4.cpu_read(0x8000), 5 * 16);
+        assert_eq!(mmc4.cpu_read(0xc000), 7 * 16);
+
+        mmc4.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc4.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_latch_switches_after_fd_fe_fetches() {
+        let mut mmc2 = with_chr_banks(Chip::Mmc2);
+        assert_eq!(mmc2.ppu_read(0x0000), 2 * 4);
+        assert_eq!(mmc2.ppu_read(0x1000), 4 * 4);
+
+        //the fetch that trips the latch still reads from the old bank
+        assert_eq!(
+            mmc2.pattern_fetch(0x0fd8, PatternFetch::Background),
+            2 * 4 + 3
+        );
+        assert_eq!(mmc2.ppu_read(0x0000), 4);
+        assert_eq!(mmc2.ppu_read(0x1000), 4 * 4);
+
+        mmc2.pattern_fetch(0x1fdb, PatternFetch::Background);
+        assert_eq!(mmc2.ppu_read(0x1000), 3 * 4);
+
+        mmc2.pattern_fetch(0x1fe8, PatternFetch::Background);
+        mmc2.pattern_fetch(0x0fe8, PatternFetch::Background);
+        assert_eq!(mmc2.ppu_read(0x0000), 2 * 4);
+        assert_eq!(mmc2.ppu_read(0x1000), 4 * 4);
+    }
+
+    #[test]
+    fn test_mmc2_first_table_only_trips_on_exact_address() {
+        let mut mmc2 = with_chr_banks(Chip::Mmc2);
+        mmc2.pattern_fetch(0x0fdb, PatternFetch::Background);
+        assert_eq!(mmc2.ppu_read(0x0000), 2 * 4);
+
+        let mut mmc4 = with_chr_banks(Chip::Mmc4);
+        mmc4.pattern_fetch(0x0fdb, PatternFetch::Background);
+        assert_eq!(mmc4.ppu_read(0x0000), 4);
+    }
+}
@@ -0,0 +1,346 @@
+// mapper 4: nintendo's mmc3. eight bank registers fed through $8000/$8001 map 8KB prg
+// and 1-2KB chr banks, and a scanline counter clocked by rises of ppu address line
+// a12 raises the cpu irq line.
+// https://www.nesdev.org/wiki/MMC3
+
+use super::{new_prg_ram, Mapper};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+// a12 has to stay low for a few cpu cycles before a rise clocks the counter, which
+// filters out the quick toggling during sprite fetches with 8x16 sprites
+const A12_LOW_CYCLES: u64 = 3;
+
+// the two documented ways the counter reacts when it reaches or is reloaded with 0
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum IrqVariant {
+    // sharp's chips (and most later ones) raise the irq whenever the counter is 0
+    // after a clock, so a latch of 0 fires on every scanline
+    Sharp,
+    // the older nec chips only raise it when the counter is decremented to 0, or is
+    // reloaded with 0 after a write to $C001
+    Nec,
+}
+
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    four_screen: bool,
+
+    bank_select: u8,
+    banks: [u8; 8],
+    horizontal_mirroring: bool,
+    prg_ram_enabled: bool,
+    prg_ram_write_protected: bool,
+
+    irq_variant: IrqVariant,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    a12: bool,
+    a12_low_since: u64,
+    cycles: u64,
+}
+
+impl Mmc3 {
+    // NES 2.0 submapper 4 marks the boards with the older nec chip (mmc3a)
+    pub fn new(rom: Rom) -> Self {
+        let irq_variant = if rom.submapper == 4 {
+            IrqVariant::Nec
+        } else {
+            IrqVariant::Sharp
+        };
+        Mmc3::with_irq_variant(rom, irq_variant)
+    }
+
+    pub fn with_irq_variant(rom: Rom, irq_variant: IrqVariant) -> Self {
+        let prg_ram = new_prg_ram(&rom);
+        Mmc3 {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr: rom.chr_rom,
+            chr_ram: rom.chr_ram,
+            four_screen: rom.screen_mirroring == Mirroring::FOUR_SCREEN,
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            horizontal_mirroring: rom.screen_mirroring == Mirroring::HORIZONTAL,
+            prg_ram_enabled: true,
+            prg_ram_write_protected: false,
+            irq_variant,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12: false,
+            a12_low_since: 0,
+            cycles: 0,
+        }
+    }
+
+    fn prg_rom_addr(&self, addr: u16) -> usize {
+        let second_last = (self.prg_rom.len() / PRG_BANK_SIZE).saturating_sub(2) as u8;
+        let swapped = self.bank_select & 0b0100_0000 != 0;
+        let bank = match (addr - 0x8000) / 0x2000 {
+            0 if swapped => second_last,
+            0 => self.banks[6],
+            1 => self.banks[7],
+            2 if swapped => self.banks[6],
+            2 => second_last,
+            _ => second_last + 1,
+        };
+        let idx = bank as usize * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1));
+        idx % self.prg_rom.len()
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        //with inversion the 2KB banks move to $1000 and the 1KB ones to $0000
+        let mut slot = addr as usize / CHR_BANK_SIZE;
+        if self.bank_select & 0b1000_0000 != 0 {
+            slot ^= 0b100;
+        }
+        let bank = match slot {
+            0 => self.banks[0] & 0xfe,
+            1 => self.banks[0] | 1,
+            2 => self.banks[1] & 0xfe,
+            3 => self.banks[1] | 1,
+            _ => self.banks[slot - 2],
+        };
+        let idx = bank as usize * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1));
+        idx % self.chr.len()
+    }
+
+    fn clock_irq_counter(&mut self) {
+        let reloaded = self.irq_reload;
+        let was = self.irq_counter;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        let fire = match self.irq_variant {
+            IrqVariant::Sharp => self.irq_counter == 0,
+            IrqVariant::Nec => self.irq_counter == 0 && (was != 0 || reloaded),
+        };
+        if fire && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.prg_rom[self.prg_rom_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        let even = addr & 1 == 0;
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled && !self.prg_ram_write_protected => {
+                self.prg_ram[(addr - 0x6000) as usize] = data;
+            }
+            0x8000..=0x9fff if even => self.bank_select = data,
+            0x8000..=0x9fff => self.banks[(self.bank_select & 0b111) as usize] = data,
+            0xa000..=0xbfff if even => self.horizontal_mirroring = data & 1 == 1,
+            0xa000..=0xbfff => {
+                self.prg_ram_enabled = data & 0b1000_0000 != 0;
+                self.prg_ram_write_protected = data & 0b0100_0000 != 0;
+            }
+            0xc000..=0xdfff if even => self.irq_latch = data,
+            0xc000..=0xdfff => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xe000..=0xffff if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xe000..=0xffff => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let idx = self.chr_addr(addr);
+            self.chr[idx] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.four_screen {
+            Mirroring::FOUR_SCREEN
+        } else if self.horizontal_mirroring {
+            Mirroring::HORIZONTAL
+        } else {
+            Mirroring::VERTICAL
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn ppu_address(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 != 0;
+        if a12 && !self.a12 && self.cycles - self.a12_low_since >= A12_LOW_CYCLES {
+            self.clock_irq_counter();
+        }
+        if !a12 && self.a12 {
+            self.a12_low_since = self.cycles;
+        }
+        self.a12 = a12;
+    }
+
+    fn scanline(&mut self) {
+        self.clock_irq_counter();
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.cycles += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::test_rom;
+
+    fn set_bank(mmc3: &mut Mmc3, register: u8, bank: u8) {
+        mmc3.cpu_write(0x8000, (mmc3.bank_select & 0b1100_0000) | register);
+        mmc3.cpu_write(0x8001, bank);
+    }
+
+    #[test]
+    fn test_prg_banks_and_mode_swap() {
+        let mut mmc3 = Mmc3::new(test_rom(4, 8, 8));
+        set_bank(&mut mmc3, 6, 3);
+        set_bank(&mut mmc3, 7, 5);
+        assert_eq!(mmc3.cpu_read(0x8000), 3 * 8);
+        assert_eq!(mmc3.cpu_read(0xa000), 5 * 8);
+        assert_eq!(mmc3.cpu_read(0xc000), 14 * 8);
+        assert_eq!(mmc3.cpu_read(0xe000), 15 * 8);
+
+        mmc3.cpu_write(0x8000, 0b0100_0000);
+        assert_eq!(mmc3.cpu_read(0x8000), 14 * 8);
+        assert_eq!(mmc3.cpu_read(0xa000), 5 * 8);
+        assert_eq!(mmc3.cpu_read(0xc000), 3 * 8);
+    }
+
+    #[test]
+    fn test_chr_banks_and_inversion() {
+        let mut mmc3 = Mmc3::new(test_rom(4, 2, 8));
+        set_bank(&mut mmc3, 0, 9);
+        set_bank(&mut mmc3, 2, 20);
+        //2KB banks ignore the low bit
+        assert_eq!(mmc3.ppu_read(0x0000), 8);
+        assert_eq!(mmc3.ppu_read(0x0400), 9);
+        assert_eq!(mmc3.ppu_read(0x1000), 20);
+
+        mmc3.cpu_write(0x8000, 0b1000_0000);
+        assert_eq!(mmc3.ppu_read(0x1000), 8);
+        assert_eq!(mmc3.ppu_read(0x1400), 9);
+        assert_eq!(mmc3.ppu_read(0x0000), 20);
+    }
+
+    #[test]
+    fn test_mirroring_and_prg_ram_protect() {
+        let mut mmc3 = Mmc3::new(test_rom(4, 2, 1));
+        mmc3.cpu_write(0xa000, 1);
+        assert_eq!(mmc3.mirroring(), Mirroring::HORIZONTAL);
+        mmc3.cpu_write(0xa000, 0);
+        assert_eq!(mmc3.mirroring(), Mirroring::VERTICAL);
+
+        mmc3.cpu_write(0x6000, 0x42);
+        mmc3.cpu_write(0xa001, 0b1100_0000);
+        mmc3.cpu_write(0x6000, 0x11);
+        assert_eq!(mmc3.cpu_read(0x6000), 0x42);
+        mmc3.cpu_write(0xa001, 0);
+        assert_eq!(mmc3.cpu_read(0x6000), 0);
+    }
+
+    #[test]
+    fn test_irq_after_latch_plus_one_scanlines() {
+        let mut mmc3 = Mmc3::new(test_rom(4, 2, 1));
+        mmc3.cpu_write(0xc000, 2);
+        mmc3.cpu_write(0xc001, 0);
+        mmc3.cpu_write(0xe001, 0);
+
+        //the first clock reloads the counter, then it counts down to 0
+        for _ in 0..2 {
+            mmc3.scanline();
+            assert!(!mmc3.irq());
+        }
+        mmc3.scanline();
+        assert!(mmc3.irq());
+
+        mmc3.cpu_write(0xe000, 0);
+        assert!(!mmc3.irq());
+    }
+
+    #[test]
+    fn test_a12_rises_clock_counter_after_filter() {
+        let mut mmc3 = Mmc3::new(test_rom(4, 2, 1));
+        mmc3.cpu_write(0xc000, 0);
+        mmc3.cpu_write(0xe001, 0);
+
+        mmc3.ppu_address(0x0000);
+        //too soon after a12 went low
+        mmc3.ppu_address(0x1000);
+        assert!(!mmc3.irq());
+
+        mmc3.ppu_address(0x0000);
+        for _ in 0..A12_LOW_CYCLES {
+            mmc3.cpu_cycle();
+        }
+        mmc3.ppu_address(0x1000);
+        //staying high doesn't clock again
+        mmc3.ppu_address(0x1008);
+        assert!(mmc3.irq());
+        assert_eq!(mmc3.irq_counter, 0);
+    }
+
+    #[test]
+    fn test_sharp_and_nec_reload_with_zero() {
+        for (variant, fires_again) in [(IrqVariant::Sharp, true), (IrqVariant::Nec, false)] {
+            let mut mmc3 = Mmc3::with_irq_variant(test_rom(4, 2, 1), variant);
+            mmc3.cpu_write(0xc000, 0);
+            mmc3.cpu_write(0xc001, 0);
+            mmc3.cpu_write(0xe001, 0);
+
+            //reloading through $C001 with a latch of 0 fires on both
+            mmc3.scanline();
+            assert!(mmc3.irq());
+            mmc3.cpu_write(0xe000, 0);
+            mmc3.cpu_write(0xe001, 0);
+
+            //after that the counter keeps being reloaded from 0
+            mmc3.scanline();
+            assert_eq!(mmc3.irq(), fires_again);
+        }
+    }
+}
@@ -0,0 +1,557 @@
+// mapper 5: nintendo's mmc5 (castlevania iii, just breed). four prg and four chr banking
+// modes, 1KB of exram usable as a nametable, as extended attributes or as plain ram, a
+// fill-mode nametable, a vertical split screen, a scanline irq and an 8x8 multiplier.
+//
+// the chip works out what the ppu is doing by watching its bus: it counts scanlines
+// from nametable fetches and tells sprite from background fetches by where they fall
+// in the line. here the ppu reports those through ppu_line and the fetch hooks, and
+// the sprite size is snooped from the cpu's $2000 writes like the chip does.
+// expansion audio is ignored.
+// https://www.nesdev.org/wiki/MMC5
+
+use super::{new_prg_ram, Mapper, Nametable, PatternFetch};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const EXRAM_SIZE: usize = 0x400;
+const ATTRIBUTE_TABLE: usize = 0x3c0;
+
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    exram: [u8; EXRAM_SIZE],
+
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    //two bits per nametable: vram page 0, vram page 1, exram, fill mode
+    nametables: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    //$5113-$5117: the prg ram bank, then the four rom/ram banks
+    prg_banks: [u8; 5],
+    //$5120-$5127 (set a, sprites) then $5128-$512B (set b, background), 10 bits each
+    chr_banks: [u16; 12],
+    chr_upper: u8,
+    //which set was written last, the one used outside of 8x16 sprite rendering
+    chr_set_b: bool,
+    tall_sprites: bool,
+
+    split_control: u8,
+    split_scroll: u8,
+    split_page: u8,
+
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    line_counter: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    //the background tile being fetched on the current line, counted from the first
+    //nametable fetch after ppu_line
+    tile: u8,
+    //the current tile comes from the split screen
+    split_tile: bool,
+    //exram byte for the current tile in extended attribute mode
+    extended_attribute: u8,
+}
+
+impl Mmc5 {
+    pub fn new(rom: Rom) -> Self {
+        let prg_ram = new_prg_ram(&rom);
+        Mmc5 {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr: rom.chr_rom,
+            chr_ram: rom.chr_ram,
+            exram: [0; EXRAM_SIZE],
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametables: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0, 0, 0, 0xff],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            chr_set_b: false,
+            tall_sprites: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_page: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            line_counter: 0,
+            multiplicand: 0xff,
+            multiplier: 0xff,
+            tile: 0,
+            split_tile: false,
+            extended_attribute: 0,
+        }
+    }
+
+    // returns the 8KB bank mapped at addr and whether it is rom (otherwise prg ram)
+    fn prg_bank(&self, addr: u16) -> (usize, bool) {
+        //register index and size of the bank in 8KB units
+        let (register, size) = match (self.prg_mode, addr) {
+            (_, 0x6000..=0x7fff) => (0, 1),
+            (0, _) => (4, 4),
+            (1 | 2, 0x8000..=0xbfff) => (2, 2),
+            (1, _) => (4, 2),
+            (2, 0xc000..=0xdfff) => (3, 1),
+            (2, _) => (4, 1),
+            (_, _) => (1 + (addr as usize - 0x8000) / PRG_BANK_SIZE, 1),
+        };
+        let value = self.prg_banks[register] as usize;
+        //$5117 always maps rom, $5113 always ram, the others pick with bit 7
+        let rom = register == 4 || (register != 0 && value & 0x80 != 0);
+        let bank = (value & 0x7f & !(size - 1)) + (addr as usize / PRG_BANK_SIZE) % size;
+        (bank, rom)
+    }
+
+    fn prg_ram_addr(&self, bank: usize, addr: u16) -> usize {
+        ((bank & 0b111) * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1)))
+            % self.prg_ram.len()
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0b10, 0b01]
+    }
+
+    fn chr_addr(&self, addr: u16, set_b: bool) -> usize {
+        let slot = addr as usize / CHR_BANK_SIZE;
+        //register and 1KB page within the bank. set b only covers 4KB, which shows up
+        //in both pattern tables
+        let (register, page) = match (self.chr_mode, set_b) {
+            (0, false) => (7, slot),
+            (1, false) => (3 + slot / 4 * 4, slot % 4),
+            (2, false) => (1 + slot / 2 * 2, slot % 2),
+            (_, false) => (slot, 0),
+            (0, true) => (11, slot),
+            (1, true) => (11, slot % 4),
+            (2, true) => (9 + slot % 4 / 2 * 2, slot % 2),
+            (_, true) => (8 + slot % 4, 0),
+        };
+        let pages = 8 >> self.chr_mode;
+        let bank = self.chr_banks[register] as usize * pages + page;
+        (bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))) % self.chr.len()
+    }
+
+    // the split screen replaces whole tiles left or right of a tile column
+    fn in_split(&self, tile: u8) -> bool {
+        if self.split_control & 0b1000_0000 == 0 || self.exram_mode > 1 || !self.in_frame {
+            return false;
+        }
+        let boundary = self.split_control & 0b1_1111;
+        if self.split_control & 0b0100_0000 != 0 {
+            tile >= boundary
+        } else {
+            tile < boundary
+        }
+    }
+
+    // the split scrolls on its own, wrapping after 30 rows like a nametable
+    fn split_y(&self) -> usize {
+        (self.split_scroll as usize + self.line_counter as usize) % 240
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x5204 => {
+                let status = (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6;
+                self.irq_pending = false;
+                status
+            }
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5c00..=0x5fff if self.exram_mode >= 2 => self.exram[(addr - 0x5c00) as usize],
+            0x6000..=0xffff => {
+                let (bank, rom) = self.prg_bank(addr);
+                if rom {
+                    let offset = addr as usize & (PRG_BANK_SIZE - 1);
+                    self.prg_rom[(bank * PRG_BANK_SIZE + offset) % self.prg_rom.len()]
+                } else {
+                    self.prg_ram[self.prg_ram_addr(bank, addr)]
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5100 => self.prg_mode = data & 0b11,
+            0x5101 => self.chr_mode = data & 0b11,
+            0x5102..=0x5103 => self.prg_ram_protect[(addr - 0x5102) as usize] = data & 0b11,
+            0x5104 => self.exram_mode = data & 0b11,
+            0x5105 => self.nametables = data,
+            0x5106 => self.fill_tile = data,
+            0x5107 => self.fill_attribute = data & 0b11,
+            0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = data,
+            0x5120..=0x512b => {
+                self.chr_banks[(addr - 0x5120) as usize] =
+                    (self.chr_upper as u16) << 8 | data as u16;
+                self.chr_set_b = addr >= 0x5128;
+            }
+            0x5130 => self.chr_upper = data & 0b11,
+            0x5200 => self.split_control = data,
+            0x5201 => self.split_scroll = data,
+            0x5202 => self.split_page = data,
+            0x5203 => self.irq_compare = data,
+            0x5204 => self.irq_enabled = data & 0b1000_0000 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            //exram is read only in mode 3
+            0x5c00..=0x5fff if self.exram_mode != 3 => self.exram[(addr - 0x5c00) as usize] = data,
+            0x6000..=0xdfff if self.prg_ram_writable() => {
+                let (bank, rom) = self.prg_bank(addr);
+                if !rom {
+                    let idx = self.prg_ram_addr(bank, addr);
+                    self.prg_ram[idx] = data;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr, self.chr_set_b)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let idx = self.chr_addr(addr, self.chr_set_b);
+            self.chr[idx] = data;
+        }
+    }
+
+    // only the arrangements that match a standard mirroring are reported as one, the
+    // ppu asks nametable() for the real layout
+    fn mirroring(&self) -> Mirroring {
+        match self.nametables {
+            0x50 => Mirroring::HORIZONTAL,
+            0x00 => Mirroring::SINGLE_SCREEN_LOWER,
+            0x55 => Mirroring::SINGLE_SCREEN_UPPER,
+            _ => Mirroring::VERTICAL,
+        }
+    }
+
+    fn nametable(&self, table: u16) -> Nametable {
+        match (self.nametables >> (table * 2)) & 0b11 {
+            0 => Nametable::Vram(0),
+            1 => Nametable::Vram(1),
+            _ => Nametable::Cartridge,
+        }
+    }
+
+    fn read_nametable(&self, addr: u16) -> u8 {
+        let table = (addr >> 10) & 0b11;
+        let offset = addr as usize & (EXRAM_SIZE - 1);
+        match (self.nametables >> (table * 2)) & 0b11 {
+            2 if self.exram_mode <= 1 => self.exram[offset],
+            2 => 0,
+            _ if offset < ATTRIBUTE_TABLE => self.fill_tile,
+            _ => self.fill_attribute * 0b0101_0101,
+        }
+    }
+
+    fn write_nametable(&mut self, addr: u16, data: u8) {
+        let table = (addr >> 10) & 0b11;
+        if (self.nametables >> (table * 2)) & 0b11 == 2 && self.exram_mode <= 1 {
+            self.exram[addr as usize & (EXRAM_SIZE - 1)] = data;
+        }
+    }
+
+    fn nametable_fetch(&mut self, addr: u16, value: u8) -> u8 {
+        let offset = addr as usize & (EXRAM_SIZE - 1);
+        if offset < ATTRIBUTE_TABLE {
+            //a nametable fetch starts the next tile
+            self.tile = self.tile.wrapping_add(1);
+            self.split_tile = self.in_split(self.tile);
+            if self.split_tile {
+                let row = self.split_y() / 8;
+                return self.exram[row * 32 + self.tile as usize % 32];
+            }
+            self.extended_attribute = self.exram[offset];
+            return value;
+        }
+
+        //attribute bytes are answered with the palette repeated for all four quadrants,
+        //so it comes out the same whichever quadrant the ppu picks
+        if self.split_tile {
+            let row = self.split_y() / 8;
+            let column = self.tile as usize % 32;
+            let attribute = self.exram[ATTRIBUTE_TABLE + row / 4 * 8 + column / 4];
+            let shift = (row % 4) / 2 * 4 + (column % 4) / 2 * 2;
+            ((attribute >> shift) & 0b11) * 0b0101_0101
+        } else if self.exram_mode == 1 {
+            (self.extended_attribute >> 6) * 0b0101_0101
+        } else {
+            value
+        }
+    }
+
+    fn pattern_fetch(&mut self, addr: u16, kind: PatternFetch) -> u8 {
+        let idx = match kind {
+            PatternFetch::Background if self.split_tile => {
+                let row = (addr as usize & 0x0ff8) | (self.split_y() % 8);
+                self.split_page as usize * 0x1000 + row
+            }
+            PatternFetch::Background if self.exram_mode == 1 => {
+                //4KB bank from the tile's exram byte, in either pattern table
+                let bank =
+                    (self.extended_attribute & 0b11_1111) as usize | (self.chr_upper as usize) << 6;
+                bank * 0x1000 + (addr as usize & 0x0fff)
+            }
+            PatternFetch::Background => self.chr_addr(addr, self.tall_sprites || self.chr_set_b),
+            PatternFetch::Sprite => self.chr_addr(addr, !self.tall_sprites && self.chr_set_b),
+        };
+        self.chr[idx % self.chr.len()]
+    }
+
+    fn ppu_line(&mut self, _scanline: u16, rendering: bool) {
+        self.tile = u8::MAX;
+        self.split_tile = false;
+        if !rendering {
+            self.in_frame = false;
+            return;
+        }
+        if !self.in_frame {
+            self.in_frame = true;
+            self.line_counter = 0;
+            self.irq_pending = false;
+        } else {
+            self.line_counter = self.line_counter.wrapping_add(1);
+            if self.line_counter == self.irq_compare {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn ppu_register_write(&mut self, addr: u16, data: u8) {
+        if addr == 0x2000 {
+            self.tall_sprites = data & 0b0010_0000 != 0;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::test_rom;
+
+    fn mmc5() -> Mmc5 {
+        Mmc5::new(test_rom(5, 8, 8))
+    }
+
+    #[test]
+    fn test_power_on_maps_last_bank_everywhere() {
+        let mut mmc5 = mmc5();
+        assert_eq!(mmc5.cpu_read(0xe000), 15 * 8);
+        assert_eq!(mmc5.cpu_read(0xfffc), 15 * 8 + 7);
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5114, 0x81);
+        mmc5.cpu_write(0x5115, 0x82);
+        mmc5.cpu_write(0x5116, 0x83);
+        mmc5.cpu_write(0x5117, 0x07);
+        assert_eq!(mmc5.cpu_read(0x8000), 8);
+        assert_eq!(mmc5.cpu_read(0xa000), 2 * 8);
+        assert_eq!(mmc5.cpu_read(0xc000), 3 * 8);
+        assert_eq!(mmc5.cpu_read(0xe000), 7 * 8);
+
+        //16KB + 8KB + 8KB, the 16KB bank ignores its low bit
+        mmc5.cpu_write(0x5100, 2);
+        mmc5.cpu_write(0x5115, 0x85);
+        assert_eq!(mmc5.cpu_read(0x8000), 4 * 8);
+        assert_eq!(mmc5.cpu_read(0xa000), 5 * 8);
+        assert_eq!(mmc5.cpu_read(0xc000), 3 * 8);
+
+        //32KB from $5117
+        mmc5.cpu_write(0x5100, 0);
+        assert_eq!(mmc5.cpu_read(0x8000), 4 * 8);
+        assert_eq!(mmc5.cpu_read(0xe000), 7 * 8);
+    }
+
+    #[test]
+    fn test_prg_ram_banks_and_write_protect() {
+        let mut mmc5 = Mmc5::new({
+            let mut rom = test_rom(5, 8, 8);
+            rom.prg_ram_size = 0x4000;
+            rom
+        });
+        mmc5.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc5.cpu_read(0x6000), 0);
+
+        mmc5.cpu_write(0x5102, 0b10);
+        mmc5.cpu_write(0x5103, 0b01);
+        mmc5.cpu_write(0x6000, 0x42);
+        mmc5.cpu_write(0x5113, 1);
+        mmc5.cpu_write(0x6000, 0x43);
+        assert_eq!(mmc5.cpu_read(0x6000), 0x43);
+
+        //ram bank 0 mapped into $8000 with bit 7 clear
+        mmc5.cpu_write(0x5114, 0x00);
+        assert_eq!(mmc5.cpu_read(0x8000), 0x42);
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5101, 3);
+        for (i, register) in (0x5120..=0x5127).enumerate() {
+            mmc5.cpu_write(register, 1 + i as u8);
+        }
+        assert_eq!(mmc5.ppu_read(0x0000), 1);
+        assert_eq!(mmc5.ppu_read(0x1c00), 8);
+
+        mmc5.cpu_write(0x5101, 1);
+        assert_eq!(mmc5.ppu_read(0x0000), 4 * 4);
+        assert_eq!(mmc5.ppu_read(0x1400), 8 * 4 + 1);
+
+        //set b covers 4KB, mirrored into both pattern tables, and is now the last written
+        mmc5.cpu_write(0x512b, 2);
+        assert_eq!(mmc5.ppu_read(0x0400), 2 * 4 + 1);
+        assert_eq!(mmc5.ppu_read(0x1400), 2 * 4 + 1);
+    }
+
+    #[test]
+    fn test_tall_sprites_use_separate_chr_sets() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5101, 0);
+        mmc5.cpu_write(0x5127, 1);
+        mmc5.cpu_write(0x512b, 2);
+        mmc5.ppu_register_write(0x2000, 0b0010_0000);
+        assert_eq!(mmc5.pattern_fetch(0x0000, PatternFetch::Sprite), 8);
+        assert_eq!(mmc5.pattern_fetch(0x0000, PatternFetch::Background), 2 * 8);
+
+        //with 8x8 sprites everything comes from the last written set
+        mmc5.ppu_register_write(0x2000, 0);
+        assert_eq!(mmc5.pattern_fetch(0x0000, PatternFetch::Sprite), 2 * 8);
+        mmc5.cpu_write(0x5127, 3);
+        assert_eq!(mmc5.pattern_fetch(0x0000, PatternFetch::Background), 3 * 8);
+    }
+
+    #[test]
+    fn test_nametable_sources() {
+        let mut mmc5 = mmc5();
+        //vram 0, vram 1, exram, fill
+        mmc5.cpu_write(0x5105, 0b11_10_01_00);
+        assert_eq!(mmc5.nametable(0), Nametable::Vram(0));
+        assert_eq!(mmc5.nametable(1), Nametable::Vram(1));
+        assert_eq!(mmc5.nametable(2), Nametable::Cartridge);
+
+        mmc5.write_nametable(0x2805, 0x66);
+        assert_eq!(mmc5.exram[5], 0x66);
+        assert_eq!(mmc5.read_nametable(0x2805), 0x66);
+
+        mmc5.cpu_write(0x5106, 0x24);
+        mmc5.cpu_write(0x5107, 0b10);
+        assert_eq!(mmc5.read_nametable(0x2c05), 0x24);
+        assert_eq!(mmc5.read_nametable(0x2fc5), 0b1010_1010);
+    }
+
+    #[test]
+    fn test_exram_cpu_access_by_mode() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5c00, 0x11);
+        assert_eq!(mmc5.cpu_read(0x5c00), 0);
+        mmc5.cpu_write(0x5104, 2);
+        assert_eq!(mmc5.cpu_read(0x5c00), 0x11);
+        mmc5.cpu_write(0x5104, 3);
+        mmc5.cpu_write(0x5c00, 0x22);
+        assert_eq!(mmc5.cpu_read(0x5c00), 0x11);
+    }
+
+    #[test]
+    fn test_extended_attributes() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5104, 1);
+        mmc5.exram[3] = 0b1100_0101;
+        mmc5.ppu_line(0, true);
+        assert_eq!(mmc5.nametable_fetch(0x2003, 0x10), 0x10);
+        assert_eq!(mmc5.nametable_fetch(0x23c0, 0), 0xff);
+        //4KB bank 5 regardless of the pattern table the ppu asked for
+        assert_eq!(mmc5.pattern_fetch(0x1010, PatternFetch::Background), 5 * 4);
+    }
+
+    #[test]
+    fn test_vertical_split() {
+        let mut mmc5 = mmc5();
+        //split the two leftmost tiles, scrolled down 9 lines, chr from 4KB page 3
+        mmc5.cpu_write(0x5200, 0b1000_0010);
+        mmc5.cpu_write(0x5201, 9);
+        mmc5.cpu_write(0x5202, 3);
+        mmc5.exram[32] = 0x41;
+        mmc5.exram[ATTRIBUTE_TABLE] = 0b0000_0011;
+
+        mmc5.ppu_line(0, true);
+        assert_eq!(mmc5.nametable_fetch(0x2000, 0x10), 0x41);
+        assert_eq!(mmc5.nametable_fetch(0x23c0, 0), 0xff);
+        let expected = mmc5.chr[3 * 0x1000 + 0x410 + 1];
+        assert_eq!(
+            mmc5.pattern_fetch(0x0410, PatternFetch::Background),
+            expected
+        );
+
+        mmc5.nametable_fetch(0x2001, 0x10);
+        mmc5.nametable_fetch(0x23c0, 0);
+        //third tile is outside the split
+        assert_eq!(mmc5.nametable_fetch(0x2002, 0x10), 0x10);
+        assert_eq!(mmc5.nametable_fetch(0x23c0, 0x12), 0x12);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5203, 2);
+        mmc5.cpu_write(0x5204, 0x80);
+        mmc5.ppu_line(0, true);
+        assert_eq!(mmc5.cpu_read(0x5204), 0b0100_0000);
+        mmc5.ppu_line(1, true);
+        assert!(!mmc5.irq());
+        mmc5.ppu_line(2, true);
+        assert!(mmc5.irq());
+        assert_eq!(mmc5.cpu_read(0x5204), 0b1100_0000);
+        assert!(!mmc5.irq());
+
+        mmc5.ppu_line(240, false);
+        assert_eq!(mmc5.cpu_read(0x5204), 0);
+    }
+
+    #[test]
+    fn test_multiplier() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5205, 200);
+        mmc5.cpu_write(0x5206, 100);
+        assert_eq!(mmc5.cpu_read(0x5205), (20000u16 & 0xff) as u8);
+        assert_eq!(mmc5.cpu_read(0x5206), (20000u16 >> 8) as u8);
+    }
+}
@@ -0,0 +1,308 @@
+// cartridge boards. the mapper owns the cartridge's memory (prg rom, prg ram, chr
+// rom/ram) and decides what the cpu sees at $4020-$FFFF and the ppu at $0000-$1FFF.
+// the bus and the ppu share one mapper, since bank switches made by cpu writes have
+// to show up in the ppu's pattern fetches.
+
+mod axrom;
+mod cnrom;
+mod four_screen;
+mod gxrom;
+mod mmc1;
+mod mmc2;
+mod mmc3;
+mod mmc5;
+mod nrom;
+mod uxrom;
+mod vrc4;
+mod vrc6;
+mod vrc7;
+mod vrc_irq;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cartridge::{Mirroring, Rom, RomError};
+
+pub use axrom::Axrom;
+pub use cnrom::Cnrom;
+pub use four_screen::FourScreen;
+pub use gxrom::Gxrom;
+pub use mmc1::Mmc1;
+pub use mmc2::{Chip as Mmc2Chip, Mmc2};
+pub use mmc3::{IrqVariant, Mmc3};
+pub use mmc5::Mmc5;
+pub use nrom::Nrom;
+pub use uxrom::Uxrom;
+pub use vrc4::Vrc4;
+pub use vrc6::Vrc6;
+pub use vrc7::Vrc7;
+
+pub type SharedMapper = Rc<RefCell<dyn Mapper>>;
+
+// where a nametable's memory is
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Nametable {
+    // one of the two 1KB pages of the console's nametable ram
+    Vram(u16),
+    // memory the board answers for itself, through read_nametable/write_nametable
+    Cartridge,
+}
+
+// what a pattern fetch made while rendering is for
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PatternFetch {
+    Background,
+    Sprite,
+}
+
+// the usual size of the work ram at $6000-$7FFF
+const PRG_RAM_SIZE: usize = 0x2000;
+
+// ines mapper numbers that `from_rom` knows how to build
+pub const SUPPORTED_MAPPERS: &[u8] = &[0, 1, 2, 3, 4, 5, 7, 9, 10, 21, 22, 23, 24, 25, 26, 66, 85];
+
+pub trait Mapper {
+    // cpu access to $4020-$FFFF. reads take &mut since some registers
+    // acknowledge interrupts or advance state when read
+    fn cpu_read(&mut self, addr: u16) -> u8;
+    fn cpu_write(&mut self, addr: u16, data: u8);
+
+    // the board's prg ram, including any banked out of $6000-$7FFF. on boards with a
+    // battery this is what gets saved between sessions
+    fn prg_ram(&self) -> &[u8] {
+        &[]
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    // ppu access to the pattern tables at $0000-$1FFF. ppu_read has no side effects,
+    // so debug views can use it freely; fetches made while rendering are reported
+    // separately through ppu_address and pattern_fetch
+    fn ppu_read(&self, addr: u16) -> u8;
+    fn ppu_write(&mut self, addr: u16, data: u8);
+
+    fn mirroring(&self) -> Mirroring;
+
+    // where each of the four nametables ($2000, $2400, $2800, $2C00) is found. follows
+    // mirroring() unless the board arranges them freely (mmc5)
+    fn nametable(&self, table: u16) -> Nametable {
+        Nametable::Vram(match self.mirroring() {
+            Mirroring::VERTICAL => table & 1,
+            Mirroring::HORIZONTAL => table >> 1,
+            Mirroring::SINGLE_SCREEN_LOWER => 0,
+            Mirroring::SINGLE_SCREEN_UPPER => 1,
+            //the last two are the board's own ram, see FourScreen
+            Mirroring::FOUR_SCREEN if table >= 2 => return Nametable::Cartridge,
+            Mirroring::FOUR_SCREEN => table,
+        })
+    }
+
+    // accesses to the nametables placed at Nametable::Cartridge
+    fn read_nametable(&self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn write_nametable(&mut self, _addr: u16, _data: u8) {}
+
+    // state of the cartridge's irq line, true while it is held low
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // called with every pattern table address the ppu fetches while rendering dot by
+    // dot, at the time it is fetched, for boards that time the ppu address bus (a12 counters)
+    fn ppu_address(&mut self, _addr: u16) {}
+
+    // called for every pattern byte fetched while rendering, in either render mode, and
+    // returns the byte. the order matches the hardware's but in scanline mode the timing
+    // doesn't, so this suits boards that react to what is fetched rather than when (chr
+    // latches), or that map background and sprite fetches differently (mmc5)
+    fn pattern_fetch(&mut self, addr: u16, _kind: PatternFetch) -> u8 {
+        self.ppu_read(addr)
+    }
+
+    // called for every nametable and attribute byte fetched while rendering, in either
+    // render mode, with the byte read. returns the byte the ppu should use instead, for
+    // boards that substitute their own tiles and attributes (mmc5)
+    fn nametable_fetch(&mut self, _addr: u16, value: u8) -> u8 {
+        value
+    }
+
+    // called once per visible and pre-render line while rendering is enabled, when the
+    // ppu draws whole scanlines at a time and individual fetches aren't reported
+    fn scanline(&mut self) {}
+
+    // called in either render mode as the ppu starts fetching the background of each
+    // line: at the line's start when drawing whole scanlines, at dot 321 of the line
+    // before it when running dot by dot. rendering tells if the line is a visible one
+    // with rendering enabled, which is how boards that count lines (mmc5) see a frame end
+    fn ppu_line(&mut self, _scanline: u16, _rendering: bool) {}
+
+    // cpu writes to the ppu registers at $2000-$2007, which also reach the cartridge's
+    // data lines. boards can snoop them to follow the ppu's settings (mmc5)
+    fn ppu_register_write(&mut self, _addr: u16, _data: u8) {}
+
+    // called once for every cpu cycle
+    fn cpu_cycle(&mut self) {}
+}
+
+pub fn from_rom(rom: Rom) -> Result<SharedMapper, RomError> {
+    let four_screen = rom.screen_mirroring == Mirroring::FOUR_SCREEN;
+    let mapper = match rom.mapper {
+        0 => shared(Nrom::new(rom), four_screen),
+        1 => shared(Mmc1::new(rom), four_screen),
+        2 => shared(Uxrom::new(rom), four_screen),
+        3 => shared(Cnrom::new(rom), four_screen),
+        4 => shared(Mmc3::new(rom), four_screen),
+        5 => shared(Mmc5::new(rom), four_screen),
+        7 => shared(Axrom::new(rom), four_screen),
+        9 => shared(Mmc2::new(rom, Mmc2Chip::Mmc2), four_screen),
+        10 => shared(Mmc2::new(rom, Mmc2Chip::Mmc4), four_screen),
+        21 | 22 | 23 | 25 => shared(Vrc4::new(rom), four_screen),
+        24 | 26 => shared(Vrc6::new(rom), four_screen),
+        66 => shared(Gxrom::new(rom), four_screen),
+        85 => shared(Vrc7::new(rom), four_screen),
+        n => return Err(RomError::UnsupportedMapper(n as u16)),
+    };
+    Ok(mapper)
+}
+
+// a board's prg ram, at the size the header gives. iNES 1.0 headers rarely fill it
+// in, so every board gets at least 8KB, which also lets test roms report through
+// $6000 on boards that never had any
+fn new_prg_ram(rom: &Rom) -> Vec<u8> {
+    vec![0; rom.prg_ram_size.max(PRG_RAM_SIZE)]
+}
+
+// the extra nametable ram is only added to boards whose header asks for it
+fn shared<M: Mapper + 'static>(mapper: M, four_screen: bool) -> SharedMapper {
+    if four_screen {
+        Rc::new(RefCell::new(FourScreen::new(mapper)))
+    } else {
+        Rc::new(RefCell::new(mapper))
+    }
+}
+
+// an ines image for mapper tests. every 1KB of prg and chr is filled with its own
+// index, so a read tells which page ended up at an address
+#[cfg(test)]
+pub(crate) fn test_rom(mapper: u8, prg_banks: u8, chr_banks: u8) -> Rom {
+    let mut raw = vec![
+        b'N',
+        b'E',
+        b'S',
+        0x1a,
+        prg_banks,
+        chr_banks,
+        (mapper & 0x0f) << 4,
+        mapper & 0xf0,
+    ];
+    raw.resize(16, 0);
+    let pages = prg_banks as usize * 16 + chr_banks as usize * 8;
+    for page in 0..pages {
+        let number = if page < prg_banks as usize * 16 {
+            page
+        } else {
+            page - prg_banks as usize * 16
+        };
+        raw.extend(std::iter::repeat_n(number as u8, 0x400));
+    }
+    Rom::new(&raw).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unsupported_mapper_is_rejected() {
+        let mut raw = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0xf0, 0xf0];
+        raw.resize(16 + 0x4000 + 0x2000, 0);
+        assert_eq!(Rom::new(&raw).err(), Some(RomError::UnsupportedMapper(255)));
+    }
+
+    #[test]
+    fn test_nes2_submapper() {
+        //mapper 23 submapper 3 (vrc2b), NES 2.0 identifier in byte 7
+        let mut raw = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0x70, 0x18, 0x30];
+        raw.resize(16 + 0x4000 + 0x2000, 0);
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!((rom.mapper, rom.submapper), (23, 3));
+    }
+
+    #[test]
+    fn test_mmc3_submapper_4_uses_nec_irq() {
+        for (submapper, fires_again) in [(0, true), (4, false)] {
+            let mut raw = vec![b'N', b'E', b'S', 0x1a, 2, 1, 0x40, 0x08, submapper << 4];
+            raw.resize(16 + 0x8000 + 0x2000, 0);
+            let mapper = from_rom(Rom::new(&raw).unwrap()).unwrap();
+            let mut mmc3 = mapper.borrow_mut();
+
+            //with a latch of 0 only sharp chips keep firing after the first reload
+            mmc3.cpu_write(0xc000, 0);
+            mmc3.cpu_write(0xc001, 0);
+            mmc3.cpu_write(0xe001, 0);
+            mmc3.scanline();
+            mmc3.cpu_write(0xe000, 0);
+            mmc3.cpu_write(0xe001, 0);
+            mmc3.scanline();
+            assert_eq!(mmc3.irq(), fires_again, "submapper {submapper}");
+        }
+    }
+
+    #[test]
+    fn test_prg_ram_sized_from_header() {
+        //mmc2 has no prg ram at all
+        assert!(from_rom(test_rom(9, 2, 1))
+            .unwrap()
+            .borrow()
+            .prg_ram()
+            .is_empty());
+        for &mapper in SUPPORTED_MAPPERS.iter().filter(|&&mapper| mapper != 9) {
+            let board = from_rom(test_rom(mapper, 2, 1)).unwrap();
+            assert_eq!(board.borrow().prg_ram().len(), 0x2000, "mapper {mapper}");
+
+            let mut rom = test_rom(mapper, 2, 1);
+            rom.prg_ram_size = 0x8000;
+            let board = from_rom(rom).unwrap();
+            assert_eq!(board.borrow().prg_ram().len(), 0x8000, "mapper {mapper}");
+        }
+    }
+
+    #[test]
+    fn test_discrete_boards_have_ram_at_6000() {
+        for mapper in [2, 3, 7, 66] {
+            let board = from_rom(test_rom(mapper, 2, 1)).unwrap();
+            board.borrow_mut().cpu_write(0x6123, 0x42);
+            assert_eq!(board.borrow_mut().cpu_read(0x6123), 0x42, "mapper {mapper}");
+        }
+    }
+
+    #[test]
+    fn test_8kb_prg_rom_is_mirrored_on_every_board() {
+        for &mapper in SUPPORTED_MAPPERS {
+            //NES 2.0 exponent size of 2^13 bytes
+            let mut raw = vec![b'N', b'E', b'S', 0x1a, 0b0011_0100, 1, mapper << 4];
+            raw.extend_from_slice(&[(mapper & 0xf0) | 0x08, 0, 0x0f]);
+            raw.resize(16, 0);
+            raw.extend((0..0x2000).map(|i| (i >> 8) as u8));
+            raw.resize(16 + 0x2000 + 0x2000, 0);
+
+            let board = from_rom(Rom::new(&raw).unwrap()).unwrap();
+            let mut board = board.borrow_mut();
+            for addr in (0x8000..=0xffffu16).step_by(0x100) {
+                let data = board.cpu_read(addr);
+                //what's switchable at power on varies, but the vectors must be there
+                if addr >= 0xe000 {
+                    assert_eq!(
+                        data,
+                        (addr >> 8) as u8 & 0x1f,
+                        "mapper {mapper} at {addr:#06x}"
+                    );
+                }
+            }
+        }
+    }
+}
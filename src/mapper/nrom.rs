@@ -0,0 +1,112 @@
+// mapper 0: no bank switching. 16KB of prg rom is mirrored into both halves of
+// $8000-$FFFF, and $6000-$7FFF holds 8KB of prg ram on the boards that have it
+
+use super::{new_prg_ram, Mapper};
+use crate::cartridge::{Mirroring, Rom};
+
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(rom: Rom) -> Self {
+        let prg_ram = new_prg_ram(&rom);
+        Nrom {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr: rom.chr_rom,
+            chr_ram: rom.chr_ram,
+            mirroring: rom.screen_mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => {
+                let idx = (addr - 0x8000) as usize % self.prg_rom.len();
+                self.prg_rom[idx]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        //writes to prg rom are ignored by the cartridge
+        if let 0x6000..=0x7fff = addr {
+            self.prg_ram[(addr - 0x6000) as usize] = data;
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr.get(addr as usize).copied().unwrap_or(0)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        //writes to chr rom are ignored by the cartridge
+        if self.chr_ram {
+            if let Some(byte) = self.chr.get_mut(addr as usize) {
+                *byte = data;
+            }
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::test_rom;
+
+    #[test]
+    fn test_16kb_prg_is_mirrored() {
+        let mut nrom = Nrom::new(test_rom(0, 1, 1));
+        assert_eq!(nrom.cpu_read(0x8400), 1);
+        assert_eq!(nrom.cpu_read(0xc400), 1);
+        assert_eq!(nrom.cpu_read(0xffff), 15);
+    }
+
+    #[test]
+    fn test_32kb_prg_is_not_mirrored() {
+        let mut nrom = Nrom::new(test_rom(0, 2, 1));
+        assert_eq!(nrom.cpu_read(0x8000), 0);
+        assert_eq!(nrom.cpu_read(0xc000), 16);
+    }
+
+    #[test]
+    fn test_prg_ram_and_rom_writes() {
+        let mut nrom = Nrom::new(test_rom(0, 1, 1));
+        nrom.cpu_write(0x6123, 0x42);
+        assert_eq!(nrom.cpu_read(0x6123), 0x42);
+        nrom.cpu_write(0x8000, 0x42);
+        assert_eq!(nrom.cpu_read(0x8000), 0);
+    }
+
+    #[test]
+    fn test_chr_rom_is_read_only() {
+        let mut nrom = Nrom::new(test_rom(0, 1, 1));
+        nrom.ppu_write(0x0400, 0x42);
+        assert_eq!(nrom.ppu_read(0x0400), 1);
+
+        let mut nrom = Nrom::new(test_rom(0, 1, 0));
+        nrom.ppu_write(0x0400, 0x42);
+        assert_eq!(nrom.ppu_read(0x0400), 0x42);
+    }
+}
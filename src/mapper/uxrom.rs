@@ -0,0 +1,105 @@
+// mapper 2: a 16KB bank switched at $8000-$BFFF, with the last bank fixed at $C000.
+// the latch sits on the data bus alongside the rom, so a write is anded with the rom
+// byte at the same address (bus conflict)
+
+use super::{new_prg_ram, Mapper};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+pub struct Uxrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    mirroring: Mirroring,
+    bank: u8,
+}
+
+impl Uxrom {
+    pub fn new(rom: Rom) -> Self {
+        let prg_ram = new_prg_ram(&rom);
+        Uxrom {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr: rom.chr_rom,
+            chr_ram: rom.chr_ram,
+            mirroring: rom.screen_mirroring,
+            bank: 0,
+        }
+    }
+
+    fn read_prg_rom(&self, addr: u16) -> u8 {
+        let bank = if addr >= 0xc000 {
+            (self.prg_rom.len() / PRG_BANK_SIZE).saturating_sub(1)
+        } else {
+            self.bank as usize
+        };
+        let idx = bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1));
+        self.prg_rom[idx % self.prg_rom.len()]
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.read_prg_rom(addr),
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize] = data,
+            0x8000..=0xffff => self.bank = data & self.read_prg_rom(addr),
+            _ => {}
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[addr as usize % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let idx = addr as usize % self.chr.len();
+            self.chr[idx] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::test_rom;
+
+    #[test]
+    fn test_switchable_and_fixed_banks() {
+        let mut uxrom = Uxrom::new(test_rom(2, 8, 0));
+        //$c800 holds page 114 (0b0111_0010), which lets bit 1 through
+        uxrom.cpu_write(0xc800, 2);
+        assert_eq!(uxrom.cpu_read(0x8000), 2 * 16);
+        assert_eq!(uxrom.cpu_read(0xc000), 7 * 16);
+    }
+
+    #[test]
+    fn test_bus_conflict_ands_with_rom() {
+        let mut uxrom = Uxrom::new(test_rom(2, 8, 0));
+        //$c400 holds page 113 (0b0111_0001)
+        uxrom.cpu_write(0xc400, 0b0000_0011);
+        assert_eq!(uxrom.cpu_read(0x8000), 16);
+    }
+}
@@ -0,0 +1,257 @@
+// mappers 21, 22, 23 and 25: konami's vrc2 and vrc4. the chips decode their register
+// addresses from two cpu address lines, and each board variant wires different ones
+// to them, which is what the mapper and submapper numbers tell apart. submapper 0
+// (and iNES 1.0) listens on both variants' lines, since their games don't clash.
+// https://www.nesdev.org/wiki/VRC2_and_VRC4
+
+use super::vrc_irq::VrcIrq;
+use super::{new_prg_ram, Mapper};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+pub struct Vrc4 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+
+    //cpu address bits wired to the chip's A0 and A1 inputs
+    a0_lines: u16,
+    a1_lines: u16,
+    vrc2: bool,
+    //vrc2a (mapper 22) drops the low bit of every chr bank number
+    chr_shift: u8,
+
+    prg_banks: [u8; 2],
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    mirroring: u8,
+    irq: VrcIrq,
+}
+
+impl Vrc4 {
+    pub fn new(rom: Rom) -> Self {
+        //(a0, a1, vrc2) for each mapper/submapper
+        let (a0_lines, a1_lines, vrc2) = match (rom.mapper, rom.submapper) {
+            (21, 1) => (0x02, 0x04, false),
+            (21, 2) => (0x40, 0x80, false),
+            (21, _) => (0x42, 0x84, false),
+            (22, _) => (0x02, 0x01, true),
+            (23, 1) => (0x01, 0x02, false),
+            (23, 2) => (0x04, 0x08, false),
+            (23, 3) => (0x01, 0x02, true),
+            (23, _) => (0x05, 0x0a, false),
+            (25, 1) => (0x02, 0x01, false),
+            (25, 2) => (0x08, 0x04, false),
+            (25, 3) => (0x02, 0x01, true),
+            (_, _) => (0x0a, 0x05, false),
+        };
+        let prg_ram = new_prg_ram(&rom);
+        Vrc4 {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr: rom.chr_rom,
+            chr_ram: rom.chr_ram,
+            a0_lines,
+            a1_lines,
+            vrc2,
+            chr_shift: (rom.mapper == 22) as u8,
+            prg_banks: [0, 1],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirroring: 0,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    // the register address as the chip sees it: $x000-$x003
+    fn register(&self, addr: u16) -> u16 {
+        let a0 = (addr & self.a0_lines != 0) as u16;
+        let a1 = (addr & self.a1_lines != 0) as u16;
+        (addr & 0xf000) | a1 << 1 | a0
+    }
+
+    fn prg_rom_addr(&self, addr: u16) -> usize {
+        let second_last = (self.prg_rom.len() / PRG_BANK_SIZE).saturating_sub(2);
+        let bank = match ((addr - 0x8000) / 0x2000, self.prg_swap) {
+            (0, false) | (2, true) => self.prg_banks[0] as usize,
+            (0, true) | (2, false) => second_last,
+            (1, _) => self.prg_banks[1] as usize,
+            _ => second_last + 1,
+        };
+        (bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))) % self.prg_rom.len()
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let bank = (self.chr_banks[addr as usize / CHR_BANK_SIZE] >> self.chr_shift) as usize;
+        (bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))) % self.chr.len()
+    }
+
+    // $B000-$E003 hold the 8 chr banks, each split over a low and a high nibble register
+    fn write_chr_bank(&mut self, register: u16, data: u8) {
+        let slot = ((register - 0xb000) >> 12) as usize * 2 + ((register >> 1) & 1) as usize;
+        let bank = &mut self.chr_banks[slot];
+        if register & 1 == 0 {
+            *bank = (*bank & 0x1f0) | (data & 0x0f) as u16;
+        } else {
+            let high_mask = if self.vrc2 { 0x0f } else { 0x1f };
+            *bank = (*bank & 0x0f) | ((data & high_mask) as u16) << 4;
+        }
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.prg_rom[self.prg_rom_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            if addr >= 0x6000 {
+                self.prg_ram[(addr - 0x6000) as usize] = data;
+            }
+            return;
+        }
+        let register = self.register(addr);
+        match register {
+            0x8000..=0x8003 => self.prg_banks[0] = data & 0b1_1111,
+            0x9000..=0x9001 if self.vrc2 => self.mirroring = data & 1,
+            0x9000..=0x9001 => self.mirroring = data & 0b11,
+            0x9002..=0x9003 if !self.vrc2 => self.prg_swap = data & 0b10 != 0,
+            0xa000..=0xa003 => self.prg_banks[1] = data & 0b1_1111,
+            0xb000..=0xefff => self.write_chr_bank(register, data),
+            _ if self.vrc2 => {}
+            0xf000 => self.irq.write_latch_low(data),
+            0xf001 => self.irq.write_latch_high(data),
+            0xf002 => self.irq.write_control(data),
+            0xf003 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let idx = self.chr_addr(addr);
+            self.chr[idx] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::VERTICAL,
+            1 => Mirroring::HORIZONTAL,
+            2 => Mirroring::SINGLE_SCREEN_LOWER,
+            _ => Mirroring::SINGLE_SCREEN_UPPER,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.cpu_cycle();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::test_rom;
+
+    fn vrc(mapper: u8, submapper: u8) -> Vrc4 {
+        let mut rom = test_rom(mapper, 8, 8);
+        rom.submapper = submapper;
+        Vrc4::new(rom)
+    }
+
+    #[test]
+    fn test_address_lines_per_submapper() {
+        //writing the $x001 register (A0) of each variant selects chr bank 1's high nibble
+        for (mapper, submapper, a0) in [
+            (21, 1, 0x02),
+            (21, 2, 0x40),
+            (23, 1, 0x01),
+            (23, 2, 0x04),
+            (25, 1, 0x02),
+            (25, 2, 0x08),
+        ] {
+            let mut vrc = vrc(mapper, submapper);
+            vrc.cpu_write(0xb000, 0x03);
+            vrc.cpu_write(0xb000 | a0, 0x01);
+            assert_eq!(vrc.chr_banks[0], 0x13, "mapper {mapper}.{submapper}");
+        }
+    }
+
+    #[test]
+    fn test_submapper_0_listens_on_both_wirings() {
+        let mut vrc = vrc(21, 0);
+        vrc.cpu_write(0xb004, 0x05);
+        assert_eq!(vrc.chr_banks[1], 0x05);
+        vrc.cpu_write(0xb080, 0x06);
+        assert_eq!(vrc.chr_banks[1], 0x06);
+    }
+
+    #[test]
+    fn test_prg_banks_and_swap_mode() {
+        let mut vrc = vrc(25, 1);
+        vrc.cpu_write(0x8000, 3);
+        vrc.cpu_write(0xa000, 4);
+        assert_eq!(vrc.cpu_read(0x8000), 3 * 8);
+        assert_eq!(vrc.cpu_read(0xa000), 4 * 8);
+        assert_eq!(vrc.cpu_read(0xc000), 14 * 8);
+        assert_eq!(vrc.cpu_read(0xe000), 15 * 8);
+
+        //$9002 on vrc4b is A1 = cpu A0
+        vrc.cpu_write(0x9001, 0b10);
+        assert_eq!(vrc.cpu_read(0x8000), 14 * 8);
+        assert_eq!(vrc.cpu_read(0xc000), 3 * 8);
+    }
+
+    #[test]
+    fn test_vrc2a_chr_banks_drop_low_bit() {
+        let mut vrc = vrc(22, 0);
+        vrc.cpu_write(0xb000, 0x07);
+        assert_eq!(vrc.ppu_read(0x0000), 3);
+    }
+
+    #[test]
+    fn test_vrc2_has_no_irq() {
+        let mut vrc = vrc(23, 3);
+        vrc.cpu_write(0xf001, 0x0f);
+        vrc.cpu_write(0xf000, 0x0f);
+        vrc.cpu_write(0xf002, 0b110);
+        vrc.cpu_cycle();
+        assert!(!vrc.irq());
+    }
+
+    #[test]
+    fn test_vrc4_irq() {
+        let mut vrc = vrc(23, 1);
+        vrc.cpu_write(0xf000, 0x0f);
+        vrc.cpu_write(0xf001, 0x0f);
+        vrc.cpu_write(0xf002, 0b110);
+        vrc.cpu_cycle();
+        assert!(vrc.irq());
+        vrc.cpu_write(0xf003, 0);
+        assert!(!vrc.irq());
+    }
+}
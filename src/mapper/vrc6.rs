@@ -0,0 +1,189 @@
+// mappers 24 and 26: konami's vrc6. a 16KB and an 8KB switchable prg bank, eight 1KB
+// chr banks and the vrc irq counter. vrc6b (26) has the A0 and A1 lines swapped.
+// the expansion audio channels ($9000-$B002) are ignored, and only the common ppu
+// banking mode 0 is supported.
+// https://www.nesdev.org/wiki/VRC6
+
+use super::vrc_irq::VrcIrq;
+use super::{new_prg_ram, Mapper};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+pub struct Vrc6 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    swapped_lines: bool,
+
+    prg_16k_bank: u8,
+    prg_8k_bank: u8,
+    chr_banks: [u8; 8],
+    mirroring: u8,
+    prg_ram_enabled: bool,
+    irq: VrcIrq,
+}
+
+impl Vrc6 {
+    pub fn new(rom: Rom) -> Self {
+        let prg_ram = new_prg_ram(&rom);
+        Vrc6 {
+            swapped_lines: rom.mapper == 26,
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr: rom.chr_rom,
+            chr_ram: rom.chr_ram,
+            prg_16k_bank: 0,
+            prg_8k_bank: 0,
+            chr_banks: [0; 8],
+            mirroring: 0,
+            prg_ram_enabled: false,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    fn register(&self, addr: u16) -> u16 {
+        if self.swapped_lines {
+            (addr & 0xf000) | (addr & 1) << 1 | (addr >> 1) & 1
+        } else {
+            addr & 0xf003
+        }
+    }
+
+    fn prg_rom_addr(&self, addr: u16) -> usize {
+        let offset = addr as usize & (PRG_BANK_SIZE - 1);
+        let bank = match addr {
+            0x8000..=0xbfff => self.prg_16k_bank as usize * 2 + (addr as usize - 0x8000) / 0x2000,
+            0xc000..=0xdfff => self.prg_8k_bank as usize,
+            _ => (self.prg_rom.len() / PRG_BANK_SIZE).saturating_sub(1),
+        };
+        (bank * PRG_BANK_SIZE + offset) % self.prg_rom.len()
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        (bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))) % self.chr.len()
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.prg_rom[self.prg_rom_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            if (0x6000..0x8000).contains(&addr) && self.prg_ram_enabled {
+                self.prg_ram[(addr - 0x6000) as usize] = data;
+            }
+            return;
+        }
+        match self.register(addr) {
+            0x8000..=0x8003 => self.prg_16k_bank = data & 0x0f,
+            0xb003 => {
+                self.mirroring = (data >> 2) & 0b11;
+                self.prg_ram_enabled = data & 0b1000_0000 != 0;
+            }
+            0xc000..=0xc003 => self.prg_8k_bank = data & 0x1f,
+            register @ (0xd000..=0xd003 | 0xe000..=0xe003) => {
+                let slot = ((register >> 12) - 0xd) * 4 + (register & 0b11);
+                self.chr_banks[slot as usize] = data;
+            }
+            0xf000 => self.irq.write_latch(data),
+            0xf001 => self.irq.write_control(data),
+            0xf002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let idx = self.chr_addr(addr);
+            self.chr[idx] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::VERTICAL,
+            1 => Mirroring::HORIZONTAL,
+            2 => Mirroring::SINGLE_SCREEN_LOWER,
+            _ => Mirroring::SINGLE_SCREEN_UPPER,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.cpu_cycle();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::test_rom;
+
+    #[test]
+    fn test_prg_banks() {
+        let mut vrc = Vrc6::new(test_rom(24, 8, 8));
+        vrc.cpu_write(0x8000, 2);
+        vrc.cpu_write(0xc000, 9);
+        assert_eq!(vrc.cpu_read(0x8000), 4 * 8);
+        assert_eq!(vrc.cpu_read(0xa000), 5 * 8);
+        assert_eq!(vrc.cpu_read(0xc000), 9 * 8);
+        assert_eq!(vrc.cpu_read(0xe000), 15 * 8);
+    }
+
+    #[test]
+    fn test_vrc6b_swaps_address_lines() {
+        let mut vrc = Vrc6::new(test_rom(26, 8, 8));
+        //$D001 on vrc6b is the chip's $D002
+        vrc.cpu_write(0xd001, 7);
+        assert_eq!(vrc.ppu_read(0x0800), 7);
+        vrc.cpu_write(0xb003, 0b1000_0100);
+        assert_eq!(vrc.mirroring(), Mirroring::HORIZONTAL);
+        assert!(vrc.prg_ram_enabled);
+    }
+
+    #[test]
+    fn test_prg_ram_enable() {
+        let mut vrc = Vrc6::new(test_rom(24, 8, 8));
+        vrc.cpu_write(0x6000, 0x55);
+        assert_eq!(vrc.cpu_read(0x6000), 0);
+        vrc.cpu_write(0xb003, 0b1000_0000);
+        vrc.cpu_write(0x6000, 0x55);
+        assert_eq!(vrc.cpu_read(0x6000), 0x55);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut vrc = Vrc6::new(test_rom(24, 8, 8));
+        vrc.cpu_write(0xf000, 0xff);
+        vrc.cpu_write(0xf001, 0b110);
+        vrc.cpu_cycle();
+        assert!(vrc.irq());
+        vrc.cpu_write(0xf002, 0);
+        assert!(!vrc.irq());
+    }
+}
@@ -0,0 +1,188 @@
+// mapper 85: konami's vrc7. three switchable 8KB prg banks, eight 1KB chr banks and
+// the vrc irq counter. the two board variants pick registers with different address
+// lines, A4 on vrc7a (submapper 2) and A3 on vrc7b (submapper 1); submapper 0 listens
+// on both. the fm synthesis audio is ignored.
+// https://www.nesdev.org/wiki/VRC7
+
+use super::vrc_irq::VrcIrq;
+use super::{new_prg_ram, Mapper};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+pub struct Vrc7 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    //cpu address bits wired to the chip's register select line
+    select_lines: u16,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    mirroring: u8,
+    prg_ram_enabled: bool,
+    irq: VrcIrq,
+}
+
+impl Vrc7 {
+    pub fn new(rom: Rom) -> Self {
+        let prg_ram = new_prg_ram(&rom);
+        let select_lines = match rom.submapper {
+            1 => 0x08,
+            2 => 0x10,
+            _ => 0x18,
+        };
+        Vrc7 {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr: rom.chr_rom,
+            chr_ram: rom.chr_ram,
+            select_lines,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            mirroring: 0,
+            prg_ram_enabled: false,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    fn prg_rom_addr(&self, addr: u16) -> usize {
+        let slot = (addr as usize - 0x8000) / PRG_BANK_SIZE;
+        let bank = match slot {
+            0..=2 => self.prg_banks[slot] as usize,
+            _ => (self.prg_rom.len() / PRG_BANK_SIZE).saturating_sub(1),
+        };
+        (bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))) % self.prg_rom.len()
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        (bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))) % self.chr.len()
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xffff => self.prg_rom[self.prg_rom_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            if (0x6000..0x8000).contains(&addr) && self.prg_ram_enabled {
+                self.prg_ram[(addr - 0x6000) as usize] = data;
+            }
+            return;
+        }
+        let select = (addr & self.select_lines != 0) as usize;
+        match (addr & 0xf000, select) {
+            (0x8000, _) => self.prg_banks[select] = data & 0x3f,
+            (0x9000, 0) => self.prg_banks[2] = data & 0x3f,
+            (0xa000..=0xd000, _) => {
+                let slot = ((addr as usize >> 12) - 0xa) * 2 + select;
+                self.chr_banks[slot] = data;
+            }
+            (0xe000, 0) => {
+                self.mirroring = data & 0b11;
+                self.prg_ram_enabled = data & 0b1000_0000 != 0;
+            }
+            (0xe000, _) => self.irq.write_latch(data),
+            (0xf000, 0) => self.irq.write_control(data),
+            (0xf000, _) => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let idx = self.chr_addr(addr);
+            self.chr[idx] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::VERTICAL,
+            1 => Mirroring::HORIZONTAL,
+            2 => Mirroring::SINGLE_SCREEN_LOWER,
+            _ => Mirroring::SINGLE_SCREEN_UPPER,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.cpu_cycle();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::test_rom;
+
+    fn vrc7(submapper: u8) -> Vrc7 {
+        let mut rom = test_rom(85, 8, 8);
+        rom.submapper = submapper;
+        Vrc7::new(rom)
+    }
+
+    #[test]
+    fn test_register_select_lines() {
+        for (submapper, select) in [(1, 0x08), (2, 0x10), (0, 0x08), (0, 0x10)] {
+            let mut vrc = vrc7(submapper);
+            vrc.cpu_write(0x8000, 3);
+            vrc.cpu_write(0x8000 | select, 4);
+            vrc.cpu_write(0x9000, 5);
+            assert_eq!(vrc.cpu_read(0x8000), 3 * 8);
+            assert_eq!(vrc.cpu_read(0xa000), 4 * 8);
+            assert_eq!(vrc.cpu_read(0xc000), 5 * 8);
+            assert_eq!(vrc.cpu_read(0xe000), 15 * 8);
+
+            vrc.cpu_write(0xd000 | select, 9);
+            assert_eq!(vrc.ppu_read(0x1c00), 9);
+        }
+    }
+
+    #[test]
+    fn test_vrc7a_ignores_a3() {
+        let mut vrc = vrc7(2);
+        vrc.cpu_write(0x8008, 3);
+        assert_eq!(vrc.prg_banks, [3, 0, 0]);
+    }
+
+    #[test]
+    fn test_mirroring_ram_and_irq() {
+        let mut vrc = vrc7(1);
+        vrc.cpu_write(0xe000, 0b1000_0011);
+        assert_eq!(vrc.mirroring(), Mirroring::SINGLE_SCREEN_UPPER);
+        vrc.cpu_write(0x6000, 0x42);
+        assert_eq!(vrc.cpu_read(0x6000), 0x42);
+
+        vrc.cpu_write(0xe008, 0xff);
+        vrc.cpu_write(0xf000, 0b110);
+        vrc.cpu_cycle();
+        assert!(vrc.irq());
+        vrc.cpu_write(0xf008, 0);
+        assert!(!vrc.irq());
+    }
+}
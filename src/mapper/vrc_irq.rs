@@ -0,0 +1,147 @@
+// the irq counter shared by konami's vrc4, vrc6 and vrc7. an 8 bit counter counts up
+// and fires when it overflows, reloading from the latch. in scanline mode a prescaler
+// approximates scanlines from cpu cycles (341 ppu dots, 3 per cpu cycle), in cycle
+// mode every cpu cycle clocks the counter directly.
+// https://www.nesdev.org/wiki/VRC_IRQ
+
+const PRESCALER_PERIOD: i16 = 341;
+const PRESCALER_STEP: i16 = 3;
+
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    //the enable bit to restore when the irq is acknowledged
+    enable_after_ack: bool,
+    enabled: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    pub fn new() -> Self {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: PRESCALER_PERIOD,
+            enable_after_ack: false,
+            enabled: false,
+            cycle_mode: false,
+            pending: false,
+        }
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        self.latch = value;
+    }
+
+    // vrc4 splits the latch over two registers
+    pub fn write_latch_low(&mut self, value: u8) {
+        self.latch = (self.latch & 0xf0) | (value & 0x0f);
+    }
+
+    pub fn write_latch_high(&mut self, value: u8) {
+        self.latch = (self.latch & 0x0f) | (value << 4);
+    }
+
+    // bit 0: enable after acknowledge, bit 1: enable, bit 2: cycle mode
+    pub fn write_control(&mut self, value: u8) {
+        self.enable_after_ack = value & 0b001 != 0;
+        self.enabled = value & 0b010 != 0;
+        self.cycle_mode = value & 0b100 != 0;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+        self.pending = false;
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    pub fn cpu_cycle(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock();
+        } else {
+            self.prescaler -= PRESCALER_STEP;
+            if self.prescaler <= 0 {
+                self.prescaler += PRESCALER_PERIOD;
+                self.clock();
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.counter == 0xff {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+impl Default for VrcIrq {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cycle_mode_fires_on_overflow() {
+        let mut irq = VrcIrq::new();
+        irq.write_latch(0xfd);
+        irq.write_control(0b110);
+        irq.cpu_cycle();
+        irq.cpu_cycle();
+        assert!(!irq.pending());
+        irq.cpu_cycle();
+        assert!(irq.pending());
+        assert_eq!(irq.counter, 0xfd);
+    }
+
+    #[test]
+    fn test_scanline_mode_prescaler() {
+        let mut irq = VrcIrq::new();
+        irq.write_latch(0xff);
+        irq.write_control(0b010);
+        //341 dots at 3 per cpu cycle is 113.67 cycles per scanline
+        for _ in 0..113 {
+            irq.cpu_cycle();
+        }
+        assert!(!irq.pending());
+        irq.cpu_cycle();
+        assert!(irq.pending());
+    }
+
+    #[test]
+    fn test_acknowledge_restores_enable_after_ack() {
+        let mut irq = VrcIrq::new();
+        irq.write_latch(0xff);
+        irq.write_control(0b110);
+        irq.cpu_cycle();
+        irq.acknowledge();
+        assert!(!irq.pending());
+        irq.cpu_cycle();
+        assert!(!irq.pending());
+
+        irq.write_control(0b111);
+        irq.cpu_cycle();
+        irq.acknowledge();
+        irq.cpu_cycle();
+        assert!(irq.pending());
+    }
+}
@@ -1,198 +0,0 @@
-use std::panic;
-
-use bitflags::bitflags;
-
-use crate::cartridge::Mirroring;
-
-pub struct PPU {
-    pub chr_rom: Vec<u8>,
-    pub palette_table: [u8; 32],
-    pub vram: [u8; 2048],
-    pub oam_data: [u8; 256],
-    pub mirroring: Mirroring,
-    pub addr: AddrRegister,
-    pub ctrl: ControlRegister,
-    internal_data_buf: u8,
-}
-
-impl PPU {
-    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
-        PPU {
-            chr_rom,
-            palette_table: [0; 32],
-            vram: [0; 2048],
-            oam_data: [0; 64 * 4],
-            mirroring,
-            addr: AddrRegister::new(),
-            ctrl: ControlRegister::new(),
-            internal_data_buf: 0u8,
-        }
-    }
-
-    pub fn write_to_ppu_addr(&mut self, value: u8) {
-        self.addr.update(value)
-    }
-
-    pub fn write_to_ctrl(&mut self, value: u8) {
-        self.ctrl.update(value)
-    }
-
-    fn increment_vram_addr(&mut self) {
-        self.addr.increment(self.ctrl.vram_addr_increment());
-    }
-
-    pub fn mirror_vram_addr(&self, addr: u16) -> u16 {
-        let mirrored_vram = addr & 0b10111111111111;
-        let vram_index = mirrored_vram - 0x2000;
-        let name_table = vram_index / 0x400;
-        match (&self.mirroring, name_table) {
-            (Mirroring::VERTICAL, 2) | (Mirroring::VERTICAL, 3) => vram_index - 0x800,
-            (Mirroring::HORIZONTAL, 2) => vram_index - 0x400,
-            (Mirroring::HORIZONTAL, 1) => vram_index - 0x400,
-            (Mirroring::HORIZONTAL, 3) => vram_index - 0x800,
-            _ => vram_index,
-        }
-    }
-
-    pub fn write_to_data(&mut self, value: u8) {
-        let addr = self.addr.get();
-
-        match addr {
-            0..=0x1fff => {
-                panic!("attempt to write to chr rom space {}", addr);
-            }
-            0x2000..=0x2fff => {
-                self.vram[self.mirror_vram_addr(addr) as usize] = value;
-            }
-            0x3000..=0x3eff => panic!("addr {} shouldn't be used", addr),
-            //Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                let add_mirror = addr - 0x10;
-                self.palette_table[(add_mirror - 0x3f00) as usize] = value
-            }
-            0x3f00..=0x3fff => self.palette_table[(addr - 0x3f00) as usize] = value,
-            _ => panic!("unexpected access to mirrored space {}", addr),
-        }
-        self.increment_vram_addr();
-    }
-
-    pub fn read_data(&mut self) -> u8 {
-        let addr = self.addr.get();
-        self.increment_vram_addr();
-
-        match addr {
-            0..=0x1fff => {
-                let result = self.internal_data_buf;
-                self.internal_data_buf = self.chr_rom[addr as usize];
-                result
-            }
-            0x2000..=0x2fff => {
-                let result = self.internal_data_buf;
-                self.internal_data_buf = self.vram[self.mirror_vram_addr(addr) as usize];
-                result
-            }
-            0x3000..=0x3eff => panic!(
-                "addr space 0x3000..0x3eff is not expected to be used, requested = {}",
-                addr
-            ),
-            0x3f00..=0x3fff => self.palette_table[(addr - 0x3f00) as usize],
-            _ => panic!("unexpected access to mirrored space {}", addr),
-        }
-    }
-}
-pub struct AddrRegister {
-    value: (u8, u8),
-    hi_ptr: bool,
-}
-
-impl AddrRegister {
-    pub fn new() -> Self {
-        AddrRegister {
-            //high byte first, low byte second
-            value: (0, 0),
-            hi_ptr: true,
-        }
-    }
-
-    pub fn get(&self) -> u16 {
-        (self.value.0 as u16) << 8 | self.value.1 as u16
-    }
-    fn set(&mut self, data: u16) {
-        self.value.0 = (data >> 8) as u8;
-        self.value.1 = (data & 0xff) as u8;
-    }
-
-    pub fn update(&mut self, data: u8) {
-        if self.hi_ptr {
-            self.value.0 = data;
-        } else {
-            self.value.1 = data;
-        }
-        if self.get() > 0x3fff {
-            self.set(self.get() & 0b11111111111111)
-        }
-        self.hi_ptr = !self.hi_ptr
-    }
-    pub fn increment(&mut self, inc: u8) {
-        let lo = self.value.1;
-        self.value.1 = self.value.1.wrapping_add(inc);
-        if lo > self.value.1 {
-            self.value.0 = self.value.0.wrapping_add(1);
-        }
-        if self.get() > 0x3fff {
-            //mirror down addr above 3fff
-            self.set(self.get() & 0b11111111111111);
-        }
-    }
-
-    pub fn reset_latch(&mut self) {
-        self.hi_ptr = true;
-    }
-}
-
-bitflags! {
-    // 7  bit  0
-    // ---- ----
-    // VPHB SINN
-    // |||| ||||
-    // |||| ||++- Base nametable address
-    // |||| ||    (0 = $2000; 1 = $2400; 2 = $2800; 3 = $2C00)
-    // |||| |+--- VRAM address increment per CPU read/write of PPUDATA
-    // |||| |     (0: add 1, going across; 1: add 32, going down)
-    // |||| +---- Sprite pattern table address for 8x8 sprites
-    // ||||       (0: $0000; 1: $1000; ignored in 8x16 mode)
-    // |||+------ Background pattern table address (0: $0000; 1: $1000)
-    // ||+------- Sprite size (0: 8x8 pixels; 1: 8x16 pixels)
-    // |+-------- PPU master/slave select
-    // |          (0: read backdrop from EXT pins; 1: output color on EXT pins)
-    // +--------- Generate an NMI at the start of the
-    //            vertical blanking interval (0: off; 1: on)
-    pub struct ControlRegister: u8 {
-        const NAMETABLE1  = 1 <<0;
-        const NAMETABLE2  = 1 <<1;
-        const VRAM_ADD_INCREMENT  = 1 <<2;
-        const SPRITE_PATTERN_ADDR  = 1 <<3;
-        const BACKGROUND_PATTERN_ADDR  = 1 <<4;
-        const SPRITE_SIZE  = 1 <<5;
-        const MASTER_SLAVE_SELECT  = 1 <<6;
-        const GENERATE_NMI  = 1 <<7;
-    }
-}
-
-impl ControlRegister {
-    pub fn new() -> Self {
-        ControlRegister::from_bits_truncate(0b00000000)
-    }
-
-    pub fn vram_addr_increment(&self) -> u8 {
-        if !self.contains(ControlRegister::VRAM_ADD_INCREMENT) {
-            1
-        } else {
-            32
-        }
-    }
-
-    pub fn update(&mut self, data: u8) {
-        self.bits = data
-    }
-}
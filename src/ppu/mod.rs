@@ -0,0 +1,1221 @@
+mod pipeline;
+
+use bitflags::bitflags;
+
+use crate::cartridge::Mirroring;
+use crate::mapper::{Nametable, PatternFetch, SharedMapper};
+use crate::region::Region;
+use crate::render;
+use crate::render::frame::Frame;
+use crate::render::palette::SystemPalette;
+
+pub struct PPU {
+    //the cartridge, which supplies the pattern tables and picks the nametable mirroring
+    mapper: SharedMapper,
+    pub palette_table: [u8; 32],
+    pub vram: [u8; 2048],
+    pub oam_addr: u8,
+    pub oam_data: [u8; 256],
+    pub addr: AddrRegister,
+    pub ctrl: ControlRegister,
+    pub mask: MaskRegister,
+    pub status: StatusRegister,
+    pub scroll: ScrollRegister,
+    internal_data_buf: u8,
+    io_latch: IoLatch,
+    total_dots: u64,
+    pub frame: Frame,
+    pub system_palette: SystemPalette,
+
+    scanline: u16,
+    cycles: usize,
+    region: Region,
+    odd_frame: bool,
+    nmi_interrupt: Option<u8>,
+    line_sprites: Vec<u8>,
+    sprite_zero_hit_dot: Option<usize>,
+
+    render_mode: RenderMode,
+    pipeline: pipeline::Pipeline,
+}
+
+// Scanline draws each line in one go when the ppu reaches it, which is fast but only
+// picks up register writes at line boundaries. Cycle runs the real per-dot fetch
+// sequence so mid-scanline writes take effect where the hardware would see them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    Scanline,
+    Cycle,
+}
+
+pub struct SpriteEvaluation {
+    //oam indices of the (at most 8) sprites in range, in oam order
+    pub sprites: Vec<u8>,
+    pub overflow: bool,
+}
+
+// a scanline lasts 341 ppu cycles. lines 0..240 are visible; where vblank starts and
+// how many lines a frame has depends on the region, the last line being the pre-render one
+const CYCLES_PER_SCANLINE: usize = 341;
+const VISIBLE_SCANLINES: u16 = 240;
+const MAX_SPRITES_PER_LINE: usize = 8;
+
+impl PPU {
+    pub fn new(mapper: SharedMapper) -> Self {
+        PPU {
+            mapper,
+            palette_table: [0; 32],
+            vram: [0; 2048],
+            oam_addr: 0,
+            oam_data: [0; 64 * 4],
+            addr: AddrRegister::new(),
+            ctrl: ControlRegister::new(),
+            mask: MaskRegister::new(),
+            status: StatusRegister::new(),
+            scroll: ScrollRegister::new(),
+            internal_data_buf: 0u8,
+            io_latch: IoLatch::new(),
+            total_dots: 0,
+            frame: Frame::new(),
+            system_palette: SystemPalette::new(),
+            scanline: 0,
+            cycles: 0,
+            region: Region::NTSC,
+            odd_frame: false,
+            nmi_interrupt: None,
+            line_sprites: Vec::new(),
+            sprite_zero_hit_dot: None,
+            render_mode: RenderMode::Scanline,
+            pipeline: pipeline::Pipeline::new(),
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    pub fn render_mode(&self) -> RenderMode {
+        self.render_mode
+    }
+
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        self.render_mode = mode;
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    // returns true once the visible part of a frame is complete and vblank starts
+    pub fn tick(&mut self, cycles: u8) -> bool {
+        self.total_dots += cycles as u64;
+        match self.render_mode {
+            RenderMode::Scanline => self.tick_scanline(cycles),
+            RenderMode::Cycle => {
+                let mut frame_complete = false;
+                for _ in 0..cycles {
+                    frame_complete |= self.step_dot();
+                }
+                frame_complete
+            }
+        }
+    }
+
+    fn start_vblank(&mut self) {
+        self.status.set_vblank_status(true);
+        if self.ctrl.generate_vblank_nmi() {
+            self.nmi_interrupt = Some(1);
+        }
+    }
+
+    fn end_vblank(&mut self) {
+        self.nmi_interrupt = None;
+        self.status.set_vblank_status(false);
+        self.status.remove(StatusRegister::SPRITE_ZERO_HIT);
+        self.status.remove(StatusRegister::SPRITE_OVERFLOW);
+    }
+
+    // on ntsc the pre-render line is one dot short on odd frames while rendering is on
+    fn skips_dot(&self) -> bool {
+        self.region.skips_odd_frame_dot()
+            && self.odd_frame
+            && self.rendering_enabled()
+            && self.scanline == self.region.pre_render_scanline()
+    }
+
+    fn start_frame(&mut self) {
+        self.scanline = 0;
+        self.odd_frame = !self.odd_frame;
+    }
+
+    fn tick_scanline(&mut self, cycles: u8) -> bool {
+        self.cycles += cycles as usize;
+        self.update_sprite_zero_hit();
+        let line_length = if self.skips_dot() {
+            CYCLES_PER_SCANLINE - 1
+        } else {
+            CYCLES_PER_SCANLINE
+        };
+        if self.cycles < line_length {
+            return false;
+        }
+        self.cycles -= line_length;
+        self.scanline += 1;
+
+        let mut frame_complete = false;
+        if self.scanline == self.region.vblank_scanline() {
+            self.start_vblank();
+            frame_complete = true;
+        }
+
+        if self.scanline == self.region.pre_render_scanline() {
+            self.end_vblank();
+        }
+
+        if self.scanline >= self.region.scanlines_per_frame() {
+            self.start_frame();
+            //nothing is evaluated on the pre-render line, so line 0 never shows sprites
+            self.line_sprites.clear();
+        }
+
+        let rendering = self.scanline < VISIBLE_SCANLINES && self.rendering_enabled();
+        self.mapper.borrow_mut().ppu_line(self.scanline, rendering);
+        if self.scanline < VISIBLE_SCANLINES {
+            self.render_scanline();
+            self.update_sprite_zero_hit();
+        }
+        let pre_render = self.scanline == self.region.pre_render_scanline();
+        if self.rendering_enabled() && (self.scanline < VISIBLE_SCANLINES || pre_render) {
+            self.mapper.borrow_mut().scanline();
+        }
+        frame_complete
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.mask.show_background() || self.mask.show_sprites()
+    }
+
+    fn render_scanline(&mut self) {
+        let y = self.scanline as usize;
+        if self.rendering_enabled() {
+            //stand-ins for the scroll updates the hardware makes at dots 256-257 of the
+            //previous line, and at dots 280-304 of the pre-render line
+            let temp = self.scroll.temp_addr();
+            if y == 0 {
+                self.addr.copy_vertical(temp);
+            } else {
+                self.addr.increment_y();
+            }
+            self.addr.copy_horizontal(temp);
+        }
+
+        let line = render::render_scanline(
+            self,
+            y,
+            self.addr.get(),
+            self.scroll.fine_x,
+            &self.line_sprites,
+        );
+        render::write_scanline(
+            &self.system_palette,
+            &self.mask,
+            &mut self.frame,
+            y,
+            &line.pixels,
+        );
+        self.sprite_zero_hit_dot = line.sprite_zero_hit.map(|x| x + 1);
+
+        //sprites found on this line are drawn on the next one
+        let evaluation = self.evaluate_sprites(y);
+        if self.rendering_enabled() && evaluation.overflow {
+            self.status.insert(StatusRegister::SPRITE_OVERFLOW);
+        }
+        self.line_sprites = evaluation.sprites;
+    }
+
+    fn update_sprite_zero_hit(&mut self) {
+        if let Some(dot) = self.sprite_zero_hit_dot {
+            if self.cycles >= dot {
+                self.status.insert(StatusRegister::SPRITE_ZERO_HIT);
+                self.sprite_zero_hit_dot = None;
+            }
+        }
+    }
+
+    pub fn evaluate_sprites(&self, scanline: usize) -> SpriteEvaluation {
+        let height = self.ctrl.sprite_height();
+        let in_range = |y: u8| {
+            let y = y as usize;
+            scanline >= y && scanline < y + height
+        };
+
+        let mut sprites = Vec::with_capacity(MAX_SPRITES_PER_LINE);
+        let mut n = 0;
+        while n < 64 && sprites.len() < MAX_SPRITES_PER_LINE {
+            if in_range(self.oam_data[n * 4]) {
+                sprites.push(n as u8);
+            }
+            n += 1;
+        }
+
+        //once secondary oam is full the hardware keeps scanning, but increments the byte
+        //offset along with the sprite index, reading tile/attribute/x bytes as y coordinates
+        let mut overflow = false;
+        let mut m = 0;
+        while n < 64 {
+            if in_range(self.oam_data[n * 4 + m]) {
+                overflow = true;
+                break;
+            }
+            n += 1;
+            m = (m + 1) & 0b11;
+        }
+
+        SpriteEvaluation { sprites, overflow }
+    }
+
+    pub fn poll_nmi_interrupt(&mut self) -> Option<u8> {
+        self.nmi_interrupt.take()
+    }
+
+    // reading a write-only register just returns what is left on the ppu's data bus
+    pub fn read_io_latch(&mut self) -> u8 {
+        self.io_latch.read(self.total_dots)
+    }
+
+    fn drive_io_latch(&mut self, value: u8, mask: u8) {
+        self.io_latch.drive(value, mask, self.total_dots);
+    }
+
+    pub fn write_to_ppu_addr(&mut self, value: u8) {
+        self.drive_io_latch(value, 0xff);
+        if self.scroll.write_addr(value) {
+            self.addr.set(self.scroll.temp_addr());
+        }
+    }
+
+    pub fn write_to_ctrl(&mut self, value: u8) {
+        self.drive_io_latch(value, 0xff);
+        let before_nmi_status = self.ctrl.generate_vblank_nmi();
+        self.ctrl.update(value);
+        self.scroll.set_nametable(value);
+        //enabling nmi while already in vblank fires it straight away
+        if !before_nmi_status && self.ctrl.generate_vblank_nmi() && self.status.is_in_vblank() {
+            self.nmi_interrupt = Some(1);
+        }
+    }
+
+    pub fn write_to_mask(&mut self, value: u8) {
+        self.drive_io_latch(value, 0xff);
+        self.mask.update(value);
+    }
+
+    pub fn write_to_scroll(&mut self, value: u8) {
+        self.drive_io_latch(value, 0xff);
+        self.scroll.write(value);
+    }
+
+    pub fn write_to_oam_addr(&mut self, value: u8) {
+        self.drive_io_latch(value, 0xff);
+        self.oam_addr = value;
+    }
+
+    pub fn write_to_oam_data(&mut self, value: u8) {
+        self.drive_io_latch(value, 0xff);
+        self.oam_data[self.oam_addr as usize] = value;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    pub fn read_oam_data(&mut self) -> u8 {
+        let mut data = self.oam_data[self.oam_addr as usize];
+        //bits 2-4 of the sprite attribute byte don't exist and read back as 0
+        if self.oam_addr & 0b11 == 2 {
+            data &= 0b1110_0011;
+        }
+        self.drive_io_latch(data, 0xff);
+        data
+    }
+
+    //dma starts copying at the current oam address and wraps around
+    pub fn write_oam_dma(&mut self, data: &[u8; 256]) {
+        for x in data.iter() {
+            self.oam_data[self.oam_addr as usize] = *x;
+            self.oam_addr = self.oam_addr.wrapping_add(1);
+        }
+    }
+
+    pub fn read_status(&mut self) -> u8 {
+        //only the top 3 bits are driven, the rest comes from the io latch
+        self.drive_io_latch(self.status.snapshot(), 0b1110_0000);
+        let data = self.read_io_latch();
+        //reading the status register clears vblank and the shared $2005/$2006 latch
+        self.status.set_vblank_status(false);
+        self.scroll.reset_latch();
+        data
+    }
+
+    fn increment_vram_addr(&mut self) {
+        self.addr.increment(self.ctrl.vram_addr_increment());
+    }
+
+    // asked of the mapper on every access, since some boards switch it while the game runs
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.borrow().mirroring()
+    }
+
+    // maps $2000-$3EFF to an index into the console's 2KB of nametable ram. None when
+    // the cartridge supplies that nametable itself (four-screen boards, mmc5)
+    pub fn mirror_vram_addr(&self, addr: u16) -> Option<u16> {
+        let mirrored_vram = addr & 0b10111111111111;
+        let vram_index = mirrored_vram - 0x2000;
+        let name_table = vram_index / 0x400;
+        let offset = vram_index % 0x400;
+        match self.mapper.borrow().nametable(name_table) {
+            Nametable::Vram(page) => Some(page * 0x400 + offset),
+            Nametable::Cartridge => None,
+        }
+    }
+
+    pub fn read_nametable(&self, addr: u16) -> u8 {
+        match self.mirror_vram_addr(addr) {
+            Some(idx) => self.vram[idx as usize],
+            None => self.mapper.borrow().read_nametable(addr),
+        }
+    }
+
+    fn write_nametable(&mut self, addr: u16, value: u8) {
+        match self.mirror_vram_addr(addr) {
+            Some(idx) => self.vram[idx as usize] = value,
+            None => self.mapper.borrow_mut().write_nametable(addr, value),
+        }
+    }
+
+    // a nametable or attribute fetch made while rendering, which the cartridge gets to
+    // observe and may answer with a different byte
+    pub fn fetch_nametable(&self, addr: u16) -> u8 {
+        let value = self.read_nametable(addr);
+        self.mapper.borrow_mut().nametable_fetch(addr, value)
+    }
+
+    pub fn read_chr(&self, addr: u16) -> u8 {
+        self.mapper.borrow().ppu_read(addr)
+    }
+
+    // a pattern fetch made while rendering, which the cartridge gets to observe.
+    // unlike read_chr this can change the cartridge's state, e.g. mmc2's chr latches
+    pub fn fetch_chr(&self, addr: u16, kind: PatternFetch) -> u8 {
+        self.mapper.borrow_mut().pattern_fetch(addr, kind)
+    }
+
+    pub fn write_to_data(&mut self, value: u8) {
+        self.drive_io_latch(value, 0xff);
+        let addr = self.addr.get() & 0x3fff;
+
+        match addr {
+            0..=0x1fff => self.mapper.borrow_mut().ppu_write(addr, value),
+            //$3000-$3EFF mirrors $2000-$2EFF
+            0x2000..=0x3eff => {
+                self.write_nametable(addr, value);
+            }
+            0x3f00..=0x3fff => self.palette_table[mirror_palette_addr(addr)] = value,
+            _ => panic!("unexpected access to mirrored space {}", addr),
+        }
+        self.increment_vram_addr();
+    }
+
+    pub fn read_data(&mut self) -> u8 {
+        let addr = self.addr.get() & 0x3fff;
+        self.increment_vram_addr();
+
+        let (data, driven) = match addr {
+            0..=0x1fff => {
+                let result = self.internal_data_buf;
+                self.internal_data_buf = self.read_chr(addr);
+                (result, 0xff)
+            }
+            //$3000-$3EFF mirrors $2000-$2EFF
+            0x2000..=0x3eff => {
+                let result = self.internal_data_buf;
+                self.internal_data_buf = self.read_nametable(addr);
+                (result, 0xff)
+            }
+            //palette reads skip the buffer, but the buffer is still refilled with
+            //the nametable byte that sits "underneath" at $2F00-$2FFF.
+            //palette entries are 6 bits wide, the top 2 come from the io latch
+            0x3f00..=0x3fff => {
+                self.internal_data_buf = self.read_nametable(addr - 0x1000);
+                (self.palette_table[mirror_palette_addr(addr)], 0b0011_1111)
+            }
+            _ => panic!("unexpected access to mirrored space {}", addr),
+        };
+        self.drive_io_latch(data, driven);
+        self.read_io_latch()
+    }
+}
+
+// roughly 600ms worth of ppu dots at the ntsc dot rate
+const IO_LATCH_DECAY_DOTS: u64 = 3_220_000;
+
+// the ppu's internal data bus. every register write, and the bits a register read
+// drives, are held here; each bit then decays to 0 unless refreshed in time
+pub struct IoLatch {
+    value: u8,
+    refreshed_at: [u64; 8],
+}
+
+impl IoLatch {
+    pub fn new() -> Self {
+        IoLatch {
+            value: 0,
+            refreshed_at: [0; 8],
+        }
+    }
+
+    pub fn drive(&mut self, value: u8, mask: u8, now: u64) {
+        self.value = (self.value & !mask) | (value & mask);
+        for (bit, refreshed_at) in self.refreshed_at.iter_mut().enumerate() {
+            if mask & (1 << bit) != 0 {
+                *refreshed_at = now;
+            }
+        }
+    }
+
+    pub fn read(&mut self, now: u64) -> u8 {
+        for (bit, refreshed_at) in self.refreshed_at.iter().enumerate() {
+            if now - refreshed_at > IO_LATCH_DECAY_DOTS {
+                self.value &= !(1 << bit);
+            }
+        }
+        self.value
+    }
+}
+
+impl Default for IoLatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// palette ram is 32 bytes mirrored across $3F00-$3FFF. Addresses $3F10/$3F14/$3F18/$3F1C
+// are in turn mirrors of $3F00/$3F04/$3F08/$3F0C
+fn mirror_palette_addr(addr: u16) -> usize {
+    let idx = (addr & 0x1f) as usize;
+    if idx >= 0x10 && idx & 0b11 == 0 {
+        idx - 0x10
+    } else {
+        idx
+    }
+}
+
+// the current vram address ("v"). while rendering its bits double as the scroll position:
+// yyy NN YYYYY XXXXX
+// ||| || ||||| +++++-- coarse X scroll
+// ||| || +++++-------- coarse Y scroll
+// ||| ++-------------- nametable select
+// +++----------------- fine Y scroll
+pub struct AddrRegister {
+    value: (u8, u8),
+}
+
+impl AddrRegister {
+    pub fn new() -> Self {
+        AddrRegister {
+            //high byte first, low byte second
+            value: (0, 0),
+        }
+    }
+
+    pub fn get(&self) -> u16 {
+        (self.value.0 as u16) << 8 | self.value.1 as u16
+    }
+
+    pub fn set(&mut self, data: u16) {
+        let data = data & 0x7fff;
+        self.value.0 = (data >> 8) as u8;
+        self.value.1 = (data & 0xff) as u8;
+    }
+
+    pub fn increment(&mut self, inc: u8) {
+        self.set(self.get().wrapping_add(inc as u16));
+    }
+
+    pub fn coarse_x(&self) -> u16 {
+        self.get() & 0b11111
+    }
+
+    pub fn coarse_y(&self) -> u16 {
+        (self.get() >> 5) & 0b11111
+    }
+
+    pub fn nametable(&self) -> u16 {
+        (self.get() >> 10) & 0b11
+    }
+
+    pub fn fine_y(&self) -> u16 {
+        (self.get() >> 12) & 0b111
+    }
+
+    // moving past the last column wraps into the horizontally adjacent nametable
+    pub fn increment_coarse_x(&mut self) {
+        let v = self.get();
+        if v & 0x001f == 31 {
+            self.set((v & !0x001f) ^ 0x0400);
+        } else {
+            self.set(v + 1);
+        }
+    }
+
+    // row 29 is the last row of tiles; rows 30 and 31 hold attributes and wrap without switching nametable
+    pub fn increment_y(&mut self) {
+        let mut v = self.get();
+        if v & 0x7000 != 0x7000 {
+            self.set(v + 0x1000);
+            return;
+        }
+        v &= !0x7000;
+        let mut y = (v & 0x03e0) >> 5;
+        if y == 29 {
+            y = 0;
+            v ^= 0x0800;
+        } else if y == 31 {
+            y = 0;
+        } else {
+            y += 1;
+        }
+        self.set((v & !0x03e0) | (y << 5));
+    }
+
+    pub fn copy_horizontal(&mut self, temp: u16) {
+        self.set((self.get() & !0x041f) | (temp & 0x041f));
+    }
+
+    pub fn copy_vertical(&mut self, temp: u16) {
+        self.set((self.get() & !0x7be0) | (temp & 0x7be0));
+    }
+}
+
+impl Default for AddrRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+bitflags! {
+    // 7  bit  0
+    // ---- ----
+    // VPHB SINN
+    // |||| ||||
+    // |||| ||++- Base nametable address
+    // |||| ||    (0 = $2000; 1 = $2400; 2 = $2800; 3 = $2C00)
+    // |||| |+--- VRAM address increment per CPU read/write of PPUDATA
+    // |||| |     (0: add 1, going across; 1: add 32, going down)
+    // |||| +---- Sprite pattern table address for 8x8 sprites
+    // ||||       (0: $0000; 1: $1000; ignored in 8x16 mode)
+    // |||+------ Background pattern table address (0: $0000; 1: $1000)
+    // ||+------- Sprite size (0: 8x8 pixels; 1: 8x16 pixels)
+    // |+-------- PPU master/slave select
+    // |          (0: read backdrop from EXT pins; 1: output color on EXT pins)
+    // +--------- Generate an NMI at the start of the
+    //            vertical blanking interval (0: off; 1: on)
+    pub struct ControlRegister: u8 {
+        const NAMETABLE1  = 1 <<0;
+        const NAMETABLE2  = 1 <<1;
+        const VRAM_ADD_INCREMENT  = 1 <<2;
+        const SPRITE_PATTERN_ADDR  = 1 <<3;
+        const BACKGROUND_PATTERN_ADDR  = 1 <<4;
+        const SPRITE_SIZE  = 1 <<5;
+        const MASTER_SLAVE_SELECT  = 1 <<6;
+        const GENERATE_NMI  = 1 <<7;
+    }
+}
+
+impl ControlRegister {
+    pub fn new() -> Self {
+        ControlRegister::from_bits_truncate(0b00000000)
+    }
+
+    pub fn nametable_addr(&self) -> u16 {
+        match self.bits & 0b11 {
+            0 => 0x2000,
+            1 => 0x2400,
+            2 => 0x2800,
+            3 => 0x2c00,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn bknd_pattern_addr(&self) -> u16 {
+        if !self.contains(ControlRegister::BACKGROUND_PATTERN_ADDR) {
+            0
+        } else {
+            0x1000
+        }
+    }
+
+    pub fn sprt_pattern_addr(&self) -> u16 {
+        if !self.contains(ControlRegister::SPRITE_PATTERN_ADDR) {
+            0
+        } else {
+            0x1000
+        }
+    }
+
+    pub fn sprite_height(&self) -> usize {
+        if !self.contains(ControlRegister::SPRITE_SIZE) {
+            8
+        } else {
+            16
+        }
+    }
+
+    pub fn generate_vblank_nmi(&self) -> bool {
+        self.contains(ControlRegister::GENERATE_NMI)
+    }
+
+    pub fn vram_addr_increment(&self) -> u8 {
+        if !self.contains(ControlRegister::VRAM_ADD_INCREMENT) {
+            1
+        } else {
+            32
+        }
+    }
+
+    pub fn update(&mut self, data: u8) {
+        self.bits = data
+    }
+}
+
+impl Default for ControlRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+bitflags! {
+    // 7  bit  0
+    // ---- ----
+    // BGRs bMmG
+    // |||| ||||
+    // |||| |||+- Greyscale (0: normal color, 1: produce a greyscale display)
+    // |||| ||+-- 1: Show background in leftmost 8 pixels of screen, 0: Hide
+    // |||| |+--- 1: Show sprites in leftmost 8 pixels of screen, 0: Hide
+    // |||| +---- 1: Show background
+    // |||+------ 1: Show sprites
+    // ||+------- Emphasize red
+    // |+-------- Emphasize green
+    // +--------- Emphasize blue
+    pub struct MaskRegister: u8 {
+        const GREYSCALE = 1 << 0;
+        const LEFTMOST_8PXL_BACKGROUND = 1 << 1;
+        const LEFTMOST_8PXL_SPRITE = 1 << 2;
+        const SHOW_BACKGROUND = 1 << 3;
+        const SHOW_SPRITES = 1 << 4;
+        const EMPHASISE_RED = 1 << 5;
+        const EMPHASISE_GREEN = 1 << 6;
+        const EMPHASISE_BLUE = 1 << 7;
+    }
+}
+
+impl MaskRegister {
+    pub fn new() -> Self {
+        MaskRegister::from_bits_truncate(0b00000000)
+    }
+
+    pub fn show_background(&self) -> bool {
+        self.contains(MaskRegister::SHOW_BACKGROUND)
+    }
+
+    pub fn show_sprites(&self) -> bool {
+        self.contains(MaskRegister::SHOW_SPRITES)
+    }
+
+    pub fn leftmost_8pxl_background(&self) -> bool {
+        self.contains(MaskRegister::LEFTMOST_8PXL_BACKGROUND)
+    }
+
+    pub fn leftmost_8pxl_sprite(&self) -> bool {
+        self.contains(MaskRegister::LEFTMOST_8PXL_SPRITE)
+    }
+
+    //red, green and blue emphasis bits as a 0-7 index
+    pub fn emphasis(&self) -> u8 {
+        self.bits >> 5
+    }
+
+    pub fn update(&mut self, data: u8) {
+        self.bits = data
+    }
+}
+
+impl Default for MaskRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+bitflags! {
+    // 7  bit  0
+    // ---- ----
+    // VSO. ....
+    // |||| ||||
+    // |||+-++++- Least significant bits previously written into a PPU register
+    // ||+------- Sprite overflow
+    // |+-------- Sprite 0 Hit
+    // +--------- Vertical blank has started (0: not in vblank; 1: in vblank)
+    pub struct StatusRegister: u8 {
+        const NOTUSED          = 0b00000001;
+        const NOTUSED2         = 0b00000010;
+        const NOTUSED3         = 0b00000100;
+        const NOTUSED4         = 0b00001000;
+        const NOTUSED5         = 0b00010000;
+        const SPRITE_OVERFLOW  = 0b00100000;
+        const SPRITE_ZERO_HIT  = 0b01000000;
+        const VBLANK_STARTED   = 0b10000000;
+    }
+}
+
+impl StatusRegister {
+    pub fn new() -> Self {
+        StatusRegister::from_bits_truncate(0b00000000)
+    }
+
+    pub fn set_vblank_status(&mut self, status: bool) {
+        self.set(StatusRegister::VBLANK_STARTED, status);
+    }
+
+    pub fn is_in_vblank(&self) -> bool {
+        self.contains(StatusRegister::VBLANK_STARTED)
+    }
+
+    pub fn snapshot(&self) -> u8 {
+        self.bits
+    }
+}
+
+impl Default for StatusRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the temporary vram address ("t") assembled by writes to $2000, $2005 and $2006,
+// together with the fine x scroll and the write toggle both registers share
+pub struct ScrollRegister {
+    temp_addr: u16,
+    pub fine_x: u8,
+    latch: bool,
+}
+
+impl ScrollRegister {
+    pub fn new() -> Self {
+        ScrollRegister {
+            temp_addr: 0,
+            fine_x: 0,
+            latch: false,
+        }
+    }
+
+    pub fn temp_addr(&self) -> u16 {
+        self.temp_addr
+    }
+
+    pub fn set_nametable(&mut self, data: u8) {
+        self.temp_addr = (self.temp_addr & !0x0c00) | ((data as u16 & 0b11) << 10);
+    }
+
+    //first write sets the horizontal offset, second write the vertical one
+    pub fn write(&mut self, data: u8) {
+        if !self.latch {
+            self.temp_addr = (self.temp_addr & !0x001f) | (data as u16 >> 3);
+            self.fine_x = data & 0b111;
+        } else {
+            self.temp_addr = (self.temp_addr & !0x73e0)
+                | ((data as u16 & 0b111) << 12)
+                | ((data as u16 & 0b1111_1000) << 2);
+        }
+        self.latch = !self.latch;
+    }
+
+    //high byte first, low byte second. returns true once both halves are written
+    pub fn write_addr(&mut self, data: u8) -> bool {
+        if !self.latch {
+            self.temp_addr = (self.temp_addr & 0x00ff) | ((data as u16 & 0b11_1111) << 8);
+        } else {
+            self.temp_addr = (self.temp_addr & 0xff00) | data as u16;
+        }
+        self.latch = !self.latch;
+        !self.latch
+    }
+
+    pub fn reset_latch(&mut self) {
+        self.latch = false;
+    }
+}
+
+impl Default for ScrollRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::Mapper;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // bare pattern table memory whose mirroring tests can change directly
+    struct TestBoard {
+        chr: Vec<u8>,
+        chr_ram: bool,
+        mirroring: Mirroring,
+    }
+
+    impl Mapper for TestBoard {
+        fn cpu_read(&mut self, _addr: u16) -> u8 {
+            0
+        }
+
+        fn cpu_write(&mut self, _addr: u16, _data: u8) {}
+
+        fn ppu_read(&self, addr: u16) -> u8 {
+            self.chr[addr as usize]
+        }
+
+        fn ppu_write(&mut self, addr: u16, data: u8) {
+            if self.chr_ram {
+                self.chr[addr as usize] = data;
+            }
+        }
+
+        fn mirroring(&self) -> Mirroring {
+            self.mirroring
+        }
+    }
+
+    fn test_board(mirroring: Mirroring, chr_ram: bool) -> Rc<RefCell<TestBoard>> {
+        Rc::new(RefCell::new(TestBoard {
+            chr: vec![0; 0x2000],
+            chr_ram,
+            mirroring,
+        }))
+    }
+
+    fn new_ppu(mirroring: Mirroring) -> PPU {
+        PPU::new(test_board(mirroring, false))
+    }
+
+    fn new_empty_rom() -> PPU {
+        new_ppu(Mirroring::HORIZONTAL)
+    }
+
+    fn set_addr(ppu: &mut PPU, addr: u16) {
+        ppu.write_to_ppu_addr((addr >> 8) as u8);
+        ppu.write_to_ppu_addr((addr & 0xff) as u8);
+    }
+
+    #[test]
+    fn test_sprites_moved_after_evaluation_are_skipped() {
+        let board = test_board(Mirroring::HORIZONTAL, true);
+        //every tile is solid colour 3, and nametables are all tile 0
+        board.borrow_mut().chr.fill(0xff);
+        let mut ppu = PPU::new(board);
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[0x03] = 0x21;
+        ppu.palette_table[0x13] = 0x16;
+        let sprite = 16..24;
+
+        //8x16 sprite 0 picked for line 20, then shrunk to 8x8 or moved below the line
+        ppu.oam_data[0..4].copy_from_slice(&[10, 0, 0, sprite.start as u8]);
+        ppu.ctrl.update(0b0010_0000);
+        let evaluation = ppu.evaluate_sprites(19);
+        assert_eq!(evaluation.sprites, [0]);
+        assert!(!evaluation.overflow);
+        let sprites = evaluation.sprites;
+
+        ppu.mask.update(0b0001_1110);
+        let line = render::render_scanline(&ppu, 20, 0, 0, &sprites);
+        assert!(line.pixels[sprite.clone()].iter().all(|&p| p == 0x16));
+        assert_eq!(line.sprite_zero_hit, Some(sprite.start));
+
+        let moves: [fn(&mut PPU); 2] = [|ppu| ppu.ctrl.update(0), |ppu| ppu.oam_data[0] = 30];
+        for apply in moves {
+            ppu.ctrl.update(0b0010_0000);
+            ppu.oam_data[0] = 10;
+            apply(&mut ppu);
+
+            //sprites only, so anything not drawn by the sprite is the backdrop
+            ppu.mask.update(0b0001_0100);
+            let line = render::render_scanline(&ppu, 20, 0, 0, &sprites);
+            assert!(line.pixels.iter().all(|&p| p == 0x0f));
+
+            //over an opaque background the moved sprite 0 must not hit either
+            ppu.mask.update(0b0001_1110);
+            let line = render::render_scanline(&ppu, 20, 0, 0, &sprites);
+            assert!(line.pixels[sprite.clone()].iter().all(|&p| p == 0x21));
+            assert_eq!(line.sprite_zero_hit, None);
+
+            let evaluation = ppu.evaluate_sprites(19);
+            assert!(evaluation.sprites.is_empty());
+            assert!(!evaluation.overflow);
+        }
+    }
+
+    #[test]
+    fn test_palette_writes_mirror_every_32_bytes() {
+        let mut ppu = new_empty_rom();
+        set_addr(&mut ppu, 0x3f25);
+        ppu.write_to_data(0x16);
+        assert_eq!(ppu.palette_table[0x05], 0x16);
+
+        set_addr(&mut ppu, 0x3fe5);
+        assert_eq!(ppu.read_data() & 0x3f, 0x16);
+    }
+
+    #[test]
+    fn test_palette_sprite_backdrop_mirrors() {
+        let mut ppu = new_empty_rom();
+        for (mirror, target) in [(0x3f10, 0), (0x3f14, 4), (0x3f18, 8), (0x3f1c, 0xc)] {
+            set_addr(&mut ppu, mirror);
+            ppu.write_to_data(0x20 + target as u8);
+            assert_eq!(ppu.palette_table[target], 0x20 + target as u8);
+        }
+
+        set_addr(&mut ppu, 0x3f04);
+        ppu.write_to_data(0x2a);
+        set_addr(&mut ppu, 0x3f14);
+        assert_eq!(ppu.read_data() & 0x3f, 0x2a);
+    }
+
+    #[test]
+    fn test_palette_sprite_backdrop_mirrors_above_3f1f() {
+        let mut ppu = new_empty_rom();
+        set_addr(&mut ppu, 0x3ffc);
+        ppu.write_to_data(0x11);
+        assert_eq!(ppu.palette_table[0x0c], 0x11);
+        assert_eq!(ppu.palette_table[0x1c], 0);
+    }
+
+    #[test]
+    fn test_palette_read_past_3f1f_does_not_panic() {
+        let mut ppu = new_empty_rom();
+        ppu.palette_table[0x1f] = 0x30;
+        set_addr(&mut ppu, 0x3fff);
+        assert_eq!(ppu.read_data() & 0x3f, 0x30);
+    }
+
+    #[test]
+    fn test_palette_read_is_not_buffered() {
+        let mut ppu = new_empty_rom();
+        ppu.palette_table[0x01] = 0x2c;
+        set_addr(&mut ppu, 0x3f01);
+        assert_eq!(ppu.read_data() & 0x3f, 0x2c);
+    }
+
+    #[test]
+    fn test_palette_read_fills_buffer_with_nametable_underneath() {
+        let mut ppu = new_empty_rom();
+        ppu.palette_table[0x01] = 0x2c;
+        set_addr(&mut ppu, 0x2f01);
+        ppu.write_to_data(0x66);
+
+        set_addr(&mut ppu, 0x3f01);
+        ppu.read_data();
+        set_addr(&mut ppu, 0x2000);
+        assert_eq!(ppu.read_data(), 0x66);
+    }
+
+    #[test]
+    fn test_3000_range_mirrors_nametables() {
+        let mut ppu = new_empty_rom();
+        set_addr(&mut ppu, 0x3005);
+        ppu.write_to_data(0x77);
+        assert_eq!(ppu.vram[0x05], 0x77);
+
+        set_addr(&mut ppu, 0x3005);
+        ppu.read_data(); //load_into_buffer
+        set_addr(&mut ppu, 0x2000);
+        assert_eq!(ppu.read_data(), 0x77);
+    }
+
+    #[test]
+    fn test_chr_ram_writes() {
+        let mut ppu = new_empty_rom();
+        set_addr(&mut ppu, 0x0010);
+        ppu.write_to_data(0x66);
+        assert_eq!(ppu.read_chr(0x10), 0);
+
+        let mut ppu = PPU::new(test_board(Mirroring::HORIZONTAL, true));
+        set_addr(&mut ppu, 0x0010);
+        ppu.write_to_data(0x66);
+        set_addr(&mut ppu, 0x0010);
+        ppu.read_data(); //load_into_buffer
+        assert_eq!(ppu.read_data(), 0x66);
+    }
+
+    #[test]
+    fn test_vertical_mirroring() {
+        let mut ppu = new_ppu(Mirroring::VERTICAL);
+        set_addr(&mut ppu, 0x2405);
+        ppu.write_to_data(0x66);
+        assert_eq!(ppu.read_nametable(0x2c05), 0x66);
+        assert_eq!(ppu.read_nametable(0x2005), 0);
+    }
+
+    #[test]
+    fn test_horizontal_mirroring() {
+        let mut ppu = new_empty_rom();
+        set_addr(&mut ppu, 0x2805);
+        ppu.write_to_data(0x66);
+        assert_eq!(ppu.read_nametable(0x2c05), 0x66);
+        assert_eq!(ppu.read_nametable(0x2405), 0);
+    }
+
+    #[test]
+    fn test_single_screen_mirroring() {
+        let board = test_board(Mirroring::SINGLE_SCREEN_UPPER, false);
+        let mut ppu = PPU::new(board.clone());
+        set_addr(&mut ppu, 0x2005);
+        ppu.write_to_data(0x66);
+        for nametable in [0x2005, 0x2405, 0x2805, 0x2c05] {
+            assert_eq!(ppu.read_nametable(nametable), 0x66);
+        }
+        assert_eq!(ppu.vram[0x405], 0x66);
+
+        board.borrow_mut().mirroring = Mirroring::SINGLE_SCREEN_LOWER;
+        assert_eq!(ppu.read_nametable(0x2c05), 0);
+    }
+
+    #[test]
+    fn test_four_screen_mirroring() {
+        let mut rom = crate::mapper::test_rom(0, 1, 1);
+        rom.screen_mirroring = Mirroring::FOUR_SCREEN;
+        let mut ppu = PPU::new(crate::mapper::from_rom(rom).unwrap());
+        for (i, nametable) in [0x2005, 0x2405, 0x2805, 0x2c05].iter().enumerate() {
+            set_addr(&mut ppu, *nametable);
+            ppu.write_to_data(i as u8 + 1);
+        }
+        for (i, nametable) in [0x2005, 0x2405, 0x2805, 0x2c05].iter().enumerate() {
+            assert_eq!(ppu.read_nametable(*nametable), i as u8 + 1);
+        }
+        assert_eq!(ppu.vram[0x005], 1);
+        assert_eq!(ppu.vram[0x405], 2);
+    }
+
+    #[test]
+    fn test_four_screen_without_board_ram() {
+        //a board claiming four-screen mirroring without the ram to back it
+        let mut ppu = new_ppu(Mirroring::FOUR_SCREEN);
+        set_addr(&mut ppu, 0x2c05);
+        ppu.write_to_data(0x42);
+        assert_eq!(ppu.read_nametable(0x2c05), 0);
+    }
+
+    #[test]
+    fn test_write_only_registers_read_io_latch() {
+        let mut ppu = new_empty_rom();
+        ppu.write_to_mask(0x5a);
+        assert_eq!(ppu.read_io_latch(), 0x5a);
+    }
+
+    #[test]
+    fn test_status_low_bits_come_from_io_latch() {
+        let mut ppu = new_empty_rom();
+        ppu.status.set_vblank_status(true);
+        ppu.write_to_oam_addr(0x1f);
+        assert_eq!(ppu.read_status(), 0x9f);
+        assert_eq!(ppu.read_status(), 0x1f);
+    }
+
+    #[test]
+    fn test_palette_read_top_bits_come_from_io_latch() {
+        let mut ppu = new_empty_rom();
+        ppu.palette_table[0x01] = 0x2c;
+        set_addr(&mut ppu, 0x3f01);
+        ppu.write_to_mask(0xc0);
+        assert_eq!(ppu.read_data(), 0xc0 | 0x2c);
+    }
+
+    #[test]
+    fn test_oam_attribute_unused_bits_read_as_zero() {
+        let mut ppu = new_empty_rom();
+        ppu.oam_data[2] = 0xff;
+        ppu.write_to_oam_addr(2);
+        assert_eq!(ppu.read_oam_data(), 0xe3);
+    }
+
+    #[test]
+    fn test_io_latch_decays() {
+        let mut ppu = new_empty_rom();
+        ppu.write_to_mask(0xff);
+        ppu.status.set_vblank_status(true);
+        ppu.total_dots += IO_LATCH_DECAY_DOTS / 2;
+        //refresh the top 3 bits only
+        ppu.read_status();
+        ppu.total_dots += IO_LATCH_DECAY_DOTS * 3 / 4;
+        assert_eq!(ppu.read_io_latch() & 0b0001_1111, 0);
+        assert_ne!(ppu.read_io_latch(), 0);
+    }
+
+    // dots between consecutive vblank starts, for two frames in a row
+    fn frame_lengths(ppu: &mut PPU) -> [usize; 2] {
+        while !ppu.tick(1) {}
+        let mut lengths = [1; 2];
+        for length in lengths.iter_mut() {
+            while !ppu.tick(1) {
+                *length += 1;
+            }
+        }
+        lengths
+    }
+
+    #[test]
+    fn test_ntsc_odd_frames_skip_a_dot_when_rendering() {
+        for mode in [RenderMode::Scanline, RenderMode::Cycle] {
+            let mut ppu = new_empty_rom();
+            ppu.set_render_mode(mode);
+            ppu.write_to_mask(0b0000_1000);
+            let lengths = frame_lengths(&mut ppu);
+            assert!(lengths.contains(&(341 * 262)));
+            assert!(lengths.contains(&(341 * 262 - 1)));
+        }
+    }
+
+    #[test]
+    fn test_ntsc_frames_keep_full_length_with_rendering_off() {
+        let mut ppu = new_empty_rom();
+        assert_eq!(frame_lengths(&mut ppu), [341 * 262; 2]);
+    }
+
+    #[test]
+    fn test_pal_and_dendy_frame_length() {
+        for region in [Region::PAL, Region::DENDY] {
+            let mut ppu = new_empty_rom();
+            ppu.set_region(region);
+            ppu.write_to_mask(0b0000_1000);
+            assert_eq!(frame_lengths(&mut ppu), [341 * 312; 2]);
+        }
+    }
+
+    #[test]
+    fn test_dendy_vblank_starts_late() {
+        let mut ppu = new_empty_rom();
+        ppu.set_region(Region::DENDY);
+        while !ppu.tick(1) {}
+        assert_eq!(ppu.scanline(), 291);
+    }
+
+    #[test]
+    fn test_nametable_read_is_buffered() {
+        let mut ppu = new_empty_rom();
+        ppu.vram[0x0105] = 0x66;
+        set_addr(&mut ppu, 0x2105);
+        ppu.read_data(); //load_into_buffer
+        assert_eq!(ppu.read_data(), 0x66);
+    }
+}
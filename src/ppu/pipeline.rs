@@ -0,0 +1,400 @@
+// dot-by-dot rendering, following the fetch timing at
+// https://www.nesdev.org/wiki/PPU_rendering
+
+use super::{StatusRegister, PPU, VISIBLE_SCANLINES};
+use crate::mapper::PatternFetch;
+use crate::render;
+
+const LAST_DOT: usize = 340;
+const SPRITE_SLOTS: usize = 8;
+
+pub struct Pipeline {
+    //latches filled by the four background fetches of each 8 dot tile slot
+    next_tile_id: u8,
+    next_tile_attr: u8,
+    next_tile_lsb: u8,
+    next_tile_msb: u8,
+
+    //16 bit shifters: the high byte is the tile being drawn, the low byte the next one
+    bg_pattern_lo: u16,
+    bg_pattern_hi: u16,
+    bg_attr_lo: u16,
+    bg_attr_hi: u16,
+
+    //sprite evaluation state for the line being scanned
+    secondary_oam: [u8; SPRITE_SLOTS * 4],
+    eval_count: usize,
+    eval_n: usize,
+    eval_m: usize,
+    eval_sprite_zero: bool,
+
+    //sprites fetched during dots 257-320, drawn on the following line
+    sprite_count: usize,
+    sprite_zero_in_line: bool,
+    sprite_pattern_lo: [u8; SPRITE_SLOTS],
+    sprite_pattern_hi: [u8; SPRITE_SLOTS],
+    sprite_attr: [u8; SPRITE_SLOTS],
+    sprite_x: [u8; SPRITE_SLOTS],
+
+    //sprites being drawn on the current line
+    line_sprite_count: usize,
+    line_sprite_zero: bool,
+    line_pattern_lo: [u8; SPRITE_SLOTS],
+    line_pattern_hi: [u8; SPRITE_SLOTS],
+    line_attr: [u8; SPRITE_SLOTS],
+    line_x: [u8; SPRITE_SLOTS],
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline {
+            next_tile_id: 0,
+            next_tile_attr: 0,
+            next_tile_lsb: 0,
+            next_tile_msb: 0,
+            bg_pattern_lo: 0,
+            bg_pattern_hi: 0,
+            bg_attr_lo: 0,
+            bg_attr_hi: 0,
+            secondary_oam: [0xff; SPRITE_SLOTS * 4],
+            eval_count: 0,
+            eval_n: 0,
+            eval_m: 0,
+            eval_sprite_zero: false,
+            sprite_count: 0,
+            sprite_zero_in_line: false,
+            sprite_pattern_lo: [0; SPRITE_SLOTS],
+            sprite_pattern_hi: [0; SPRITE_SLOTS],
+            sprite_attr: [0; SPRITE_SLOTS],
+            sprite_x: [0; SPRITE_SLOTS],
+            line_sprite_count: 0,
+            line_sprite_zero: false,
+            line_pattern_lo: [0; SPRITE_SLOTS],
+            line_pattern_hi: [0; SPRITE_SLOTS],
+            line_attr: [0; SPRITE_SLOTS],
+            line_x: [0; SPRITE_SLOTS],
+        }
+    }
+
+    fn shift_background(&mut self) {
+        self.bg_pattern_lo <<= 1;
+        self.bg_pattern_hi <<= 1;
+        self.bg_attr_lo <<= 1;
+        self.bg_attr_hi <<= 1;
+    }
+
+    fn load_background_shifters(&mut self) {
+        self.bg_pattern_lo = (self.bg_pattern_lo & 0xff00) | self.next_tile_lsb as u16;
+        self.bg_pattern_hi = (self.bg_pattern_hi & 0xff00) | self.next_tile_msb as u16;
+        //attribute bits apply to the whole tile, so they are expanded across all 8 pixels
+        let attr_lo = if self.next_tile_attr & 0b01 != 0 {
+            0xff
+        } else {
+            0x00
+        };
+        let attr_hi = if self.next_tile_attr & 0b10 != 0 {
+            0xff
+        } else {
+            0x00
+        };
+        self.bg_attr_lo = (self.bg_attr_lo & 0xff00) | attr_lo;
+        self.bg_attr_hi = (self.bg_attr_hi & 0xff00) | attr_hi;
+    }
+
+    fn start_sprite_evaluation(&mut self) {
+        self.secondary_oam = [0xff; SPRITE_SLOTS * 4];
+        self.eval_count = 0;
+        self.eval_n = 0;
+        self.eval_m = 0;
+        self.eval_sprite_zero = false;
+    }
+
+    fn start_line(&mut self) {
+        self.line_sprite_count = self.sprite_count;
+        self.line_sprite_zero = self.sprite_zero_in_line;
+        self.line_pattern_lo = self.sprite_pattern_lo;
+        self.line_pattern_hi = self.sprite_pattern_hi;
+        self.line_attr = self.sprite_attr;
+        self.line_x = self.sprite_x;
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PPU {
+    // advances the ppu by a single dot, returns true when vblank starts
+    pub(super) fn step_dot(&mut self) -> bool {
+        let dot = self.cycles;
+        let scanline = self.scanline;
+        let visible = scanline < VISIBLE_SCANLINES;
+        let pre_render = scanline == self.region.pre_render_scanline();
+        let mut frame_complete = false;
+
+        if visible && dot == 0 {
+            self.pipeline.start_line();
+        }
+
+        if dot == 321 {
+            //fetches from here on are for the next line's first tiles
+            let next = if pre_render { 0 } else { scanline + 1 };
+            let rendering = next < VISIBLE_SCANLINES && self.rendering_enabled();
+            self.mapper.borrow_mut().ppu_line(next, rendering);
+        }
+
+        if self.rendering_enabled() && (visible || pre_render) {
+            self.fetch_background(dot, pre_render);
+
+            if visible && dot == 1 {
+                self.pipeline.start_sprite_evaluation();
+            }
+            if visible && (65..=256).contains(&dot) && dot % 2 == 1 {
+                self.evaluate_sprite_step();
+            }
+            if (257..=320).contains(&dot) {
+                self.oam_addr = 0;
+                self.fetch_sprite(dot, pre_render);
+            }
+        }
+
+        if visible && (1..=256).contains(&dot) {
+            self.output_pixel(dot - 1);
+        }
+
+        if scanline == self.region.vblank_scanline() && dot == 1 {
+            self.start_vblank();
+            frame_complete = true;
+        }
+        if pre_render && dot == 1 {
+            self.end_vblank();
+        }
+
+        let last_dot = if self.skips_dot() {
+            LAST_DOT - 1
+        } else {
+            LAST_DOT
+        };
+        self.cycles += 1;
+        if self.cycles > last_dot {
+            self.cycles = 0;
+            self.scanline += 1;
+            if self.scanline >= self.region.scanlines_per_frame() {
+                self.start_frame();
+            }
+        }
+        frame_complete
+    }
+
+    fn fetch_background(&mut self, dot: usize, pre_render: bool) {
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.pipeline.shift_background();
+        }
+
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            let v = self.addr.get();
+            match (dot - 1) % 8 {
+                0 => {
+                    self.pipeline.load_background_shifters();
+                    self.pipeline.next_tile_id = self.fetch_nametable(0x2000 | (v & 0x0fff));
+                }
+                2 => {
+                    let attr_addr = 0x23c0 | (v & 0x0c00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                    let mut attr = self.fetch_nametable(attr_addr);
+                    if self.addr.coarse_y() & 0b10 != 0 {
+                        attr >>= 4;
+                    }
+                    if self.addr.coarse_x() & 0b10 != 0 {
+                        attr >>= 2;
+                    }
+                    self.pipeline.next_tile_attr = attr & 0b11;
+                }
+                4 => {
+                    let addr = self.background_tile_addr();
+                    self.pipeline.next_tile_lsb =
+                        self.fetch_pattern(addr, PatternFetch::Background);
+                }
+                6 => {
+                    let addr = self.background_tile_addr() + 8;
+                    self.pipeline.next_tile_msb =
+                        self.fetch_pattern(addr, PatternFetch::Background);
+                }
+                7 => self.addr.increment_coarse_x(),
+                _ => {}
+            }
+        }
+
+        if dot == 256 {
+            self.addr.increment_y();
+        }
+        if dot == 257 {
+            self.pipeline.load_background_shifters();
+            self.addr.copy_horizontal(self.scroll.temp_addr());
+        }
+        if pre_render && (280..=304).contains(&dot) {
+            self.addr.copy_vertical(self.scroll.temp_addr());
+        }
+    }
+
+    //fetches made dot by dot also put their address on the bus at the right time
+    fn fetch_pattern(&mut self, addr: u16, kind: PatternFetch) -> u8 {
+        self.mapper.borrow_mut().ppu_address(addr);
+        self.fetch_chr(addr, kind)
+    }
+
+    fn background_tile_addr(&self) -> u16 {
+        self.ctrl.bknd_pattern_addr() + self.pipeline.next_tile_id as u16 * 16 + self.addr.fine_y()
+    }
+
+    //one oam entry is examined every other dot between 65 and 256
+    fn evaluate_sprite_step(&mut self) {
+        let p = &mut self.pipeline;
+        if p.eval_n >= 64 {
+            return;
+        }
+        let scanline = self.scanline as usize;
+        let height = self.ctrl.sprite_height();
+        let in_range = |y: u8| scanline >= y as usize && scanline < y as usize + height;
+
+        if p.eval_count < SPRITE_SLOTS {
+            let n = p.eval_n;
+            if in_range(self.oam_data[n * 4]) {
+                let slot = p.eval_count * 4;
+                p.secondary_oam[slot..slot + 4].copy_from_slice(&self.oam_data[n * 4..n * 4 + 4]);
+                if n == 0 {
+                    p.eval_sprite_zero = true;
+                }
+                p.eval_count += 1;
+            }
+            p.eval_n += 1;
+        } else {
+            //the hardware bug: with secondary oam full, the byte offset is incremented along
+            //with the sprite index, so tile/attribute/x bytes get compared as y coordinates
+            if in_range(self.oam_data[p.eval_n * 4 + p.eval_m]) {
+                self.status.insert(StatusRegister::SPRITE_OVERFLOW);
+                p.eval_n = 64;
+            } else {
+                p.eval_n += 1;
+                p.eval_m = (p.eval_m + 1) & 0b11;
+            }
+        }
+    }
+
+    //each of the 8 slots gets 8 dots: two garbage nametable reads, then the pattern bytes
+    fn fetch_sprite(&mut self, dot: usize, pre_render: bool) {
+        let slot = (dot - 257) / 8;
+        let phase = (dot - 257) % 8;
+        if phase != 4 && phase != 6 {
+            return;
+        }
+        //nothing is evaluated on the pre-render line, so line 0 never shows sprites
+        if slot == 0 && phase == 4 {
+            self.pipeline.sprite_count = if pre_render {
+                0
+            } else {
+                self.pipeline.eval_count
+            };
+            self.pipeline.sprite_zero_in_line = !pre_render && self.pipeline.eval_sprite_zero;
+        }
+
+        let used = slot < self.pipeline.sprite_count;
+        let (y, tile, attr, x) = if used {
+            let s = &self.pipeline.secondary_oam[slot * 4..slot * 4 + 4];
+            (s[0], s[1], s[2], s[3])
+        } else {
+            (0xff, 0xff, 0xff, 0xff)
+        };
+
+        let height = self.ctrl.sprite_height();
+        let mut row = (self.scanline as usize).wrapping_sub(y as usize) % height;
+        if attr & 0b1000_0000 != 0 {
+            row = height - 1 - row;
+        }
+        let addr = if height == 16 {
+            let bank = (tile as u16 & 1) * 0x1000;
+            let mut tile = tile as u16 & 0xfe;
+            if row >= 8 {
+                tile += 1;
+                row -= 8;
+            }
+            bank + tile * 16 + row as u16
+        } else {
+            self.ctrl.sprt_pattern_addr() + tile as u16 * 16 + row as u16
+        };
+
+        let mut data = if phase == 4 {
+            self.fetch_pattern(addr, PatternFetch::Sprite)
+        } else {
+            self.fetch_pattern(addr + 8, PatternFetch::Sprite)
+        };
+        if !used {
+            data = 0;
+        } else if attr & 0b0100_0000 != 0 {
+            data = data.reverse_bits();
+        }
+
+        let p = &mut self.pipeline;
+        if phase == 4 {
+            p.sprite_pattern_lo[slot] = data;
+        } else {
+            p.sprite_pattern_hi[slot] = data;
+            p.sprite_attr[slot] = attr;
+            p.sprite_x[slot] = x;
+        }
+    }
+
+    fn output_pixel(&mut self, x: usize) {
+        let y = self.scanline as usize;
+        let p = &self.pipeline;
+
+        let mut bg_value = 0;
+        let mut bg_palette = 0;
+        if self.mask.show_background() && (x >= 8 || self.mask.leftmost_8pxl_background()) {
+            let mux = 0x8000 >> self.scroll.fine_x;
+            let lo = (p.bg_pattern_lo & mux != 0) as u8;
+            let hi = (p.bg_pattern_hi & mux != 0) as u8;
+            bg_value = hi << 1 | lo;
+            let attr_lo = (p.bg_attr_lo & mux != 0) as u8;
+            let attr_hi = (p.bg_attr_hi & mux != 0) as u8;
+            bg_palette = attr_hi << 1 | attr_lo;
+        }
+
+        let mut palette_idx = render::bg_palette_entry(self, bg_palette, bg_value);
+        let mut sprite_zero_hit = false;
+
+        if self.mask.show_sprites() && (x >= 8 || self.mask.leftmost_8pxl_sprite()) {
+            for slot in 0..p.line_sprite_count {
+                let offset = x.wrapping_sub(p.line_x[slot] as usize);
+                if offset >= 8 {
+                    continue;
+                }
+                let value = render::tile_pixel(
+                    p.line_pattern_lo[slot],
+                    p.line_pattern_hi[slot],
+                    7 - offset,
+                );
+                if value == 0 {
+                    continue;
+                }
+
+                if slot == 0 && p.line_sprite_zero && bg_value != 0 && x != 255 {
+                    sprite_zero_hit = true;
+                }
+
+                let attr = p.line_attr[slot];
+                if attr & 0b0010_0000 == 0 || bg_value == 0 {
+                    palette_idx = self.palette_table[(0x10 + (attr & 0b11) * 4 + value) as usize];
+                }
+                break;
+            }
+        }
+
+        if sprite_zero_hit {
+            self.status.insert(StatusRegister::SPRITE_ZERO_HIT);
+        }
+        let rgb = render::color(&self.system_palette, &self.mask, palette_idx);
+        self.frame.set_pixel(x, y, rgb);
+    }
+}
@@ -0,0 +1,44 @@
+// console video standards. they differ in frame length, in how many ppu dots run per
+// cpu cycle and in where vblank begins
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum Region {
+    NTSC,
+    PAL,
+    // famiclones sold in russia: pal frame length with ntsc cpu/ppu ratio
+    DENDY,
+}
+
+impl Region {
+    pub fn scanlines_per_frame(&self) -> u16 {
+        match self {
+            Region::NTSC => 262,
+            Region::PAL | Region::DENDY => 312,
+        }
+    }
+
+    pub fn vblank_scanline(&self) -> u16 {
+        match self {
+            Region::NTSC | Region::PAL => 241,
+            // dendy idles for 51 lines after the picture before raising vblank
+            Region::DENDY => 291,
+        }
+    }
+
+    pub fn pre_render_scanline(&self) -> u16 {
+        self.scanlines_per_frame() - 1
+    }
+
+    // only the ntsc ppu drops a dot from the pre-render line on odd frames
+    pub fn skips_odd_frame_dot(&self) -> bool {
+        *self == Region::NTSC
+    }
+
+    // ppu dots per cpu cycle as a fraction (numerator, denominator)
+    pub fn ppu_dots_per_cpu_cycle(&self) -> (u32, u32) {
+        match self {
+            Region::NTSC | Region::DENDY => (3, 1),
+            Region::PAL => (16, 5),
+        }
+    }
+}
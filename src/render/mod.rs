@@ -0,0 +1,257 @@
+pub mod frame;
+pub mod palette;
+pub mod png;
+pub mod viewer;
+
+use crate::mapper::PatternFetch;
+use crate::ppu::{AddrRegister, MaskRegister, PPU};
+use frame::Frame;
+use palette::SystemPalette;
+
+const SPRITE_PALETTES: u8 = 0x10;
+
+// one rendered line of palette ram values, before conversion to rgb
+pub struct Scanline {
+    pub pixels: [u8; Frame::WIDTH],
+    pub sprite_zero_hit: Option<usize>,
+}
+
+// renders a whole frame from the current ppu state, scrolled as set up by the last
+// $2000/$2005/$2006 writes
+pub fn render(ppu: &PPU, frame: &mut Frame) {
+    let temp = ppu.scroll.temp_addr();
+    let mut vram_addr = AddrRegister::new();
+    vram_addr.set(temp);
+
+    for y in 0..Frame::HEIGHT {
+        //sprites shown on a line are the ones evaluated during the line above it
+        let sprites = if y == 0 {
+            Vec::new()
+        } else {
+            ppu.evaluate_sprites(y - 1).sprites
+        };
+        let line = render_scanline(ppu, y, vram_addr.get(), ppu.scroll.fine_x, &sprites);
+        write_scanline(&ppu.system_palette, &ppu.mask, frame, y, &line.pixels);
+        vram_addr.increment_y();
+        vram_addr.copy_horizontal(temp);
+    }
+}
+
+pub fn write_scanline(
+    system_palette: &SystemPalette,
+    mask: &MaskRegister,
+    frame: &mut Frame,
+    y: usize,
+    pixels: &[u8; Frame::WIDTH],
+) {
+    for (x, palette_idx) in pixels.iter().enumerate() {
+        frame.set_pixel(x, y, color(system_palette, mask, *palette_idx));
+    }
+}
+
+// vram_addr carries the scroll position of the line's first tile, laid out like
+// the ppu's internal "v" register
+pub fn render_scanline(
+    ppu: &PPU,
+    y: usize,
+    vram_addr: u16,
+    fine_x: u8,
+    sprites: &[u8],
+) -> Scanline {
+    let mut pixels = [ppu.palette_table[0]; Frame::WIDTH];
+    let mut bg_opaque = [false; Frame::WIDTH];
+
+    if ppu.mask.show_background() {
+        render_background_scanline(ppu, vram_addr, fine_x, &mut pixels, &mut bg_opaque);
+    }
+
+    let mut sprite_zero_hit = None;
+    if ppu.mask.show_sprites() {
+        sprite_zero_hit = render_sprites_scanline(ppu, y, sprites, &mut pixels, &bg_opaque);
+    }
+
+    Scanline {
+        pixels,
+        sprite_zero_hit,
+    }
+}
+
+pub fn color(system_palette: &SystemPalette, mask: &MaskRegister, palette_idx: u8) -> (u8, u8, u8) {
+    let mut idx = palette_idx & 0x3f;
+    if mask.contains(MaskRegister::GREYSCALE) {
+        idx &= 0x30;
+    }
+    system_palette.rgb(idx, mask.emphasis())
+}
+
+pub fn bg_palette_entry(ppu: &PPU, palette: u8, value: u8) -> u8 {
+    if value == 0 {
+        // colour 0 of every background palette is the universal backdrop at $3F00
+        ppu.palette_table[0]
+    } else {
+        ppu.palette_table[(palette * 4 + value) as usize]
+    }
+}
+
+pub fn tile_pixel(lower: u8, upper: u8, bit: usize) -> u8 {
+    ((upper >> bit) & 1) << 1 | ((lower >> bit) & 1)
+}
+
+fn render_background_scanline(
+    ppu: &PPU,
+    vram_addr: u16,
+    fine_x: u8,
+    pixels: &mut [u8; Frame::WIDTH],
+    opaque: &mut [bool; Frame::WIDTH],
+) {
+    let bank = ppu.ctrl.bknd_pattern_addr();
+    let coarse_x = (vram_addr & 0b11111) as usize;
+    let coarse_y = ((vram_addr >> 5) & 0b11111) as usize;
+    let base_nametable = ((vram_addr >> 10) & 0b11) as usize;
+    let fine_y = (vram_addr >> 12) & 0b111;
+
+    //tiles are fetched once each, left to right, so the cartridge sees the same
+    //sequence of pattern fetches as during real rendering
+    let mut tile = None;
+    for x in 0..Frame::WIDTH {
+        let scrolled_x = x + fine_x as usize;
+        let column = scrolled_x / 8;
+        let (lower, upper, palette) = match tile {
+            Some((fetched_column, fetched)) if fetched_column == column => fetched,
+            _ => {
+                let fetched = fetch_background_tile(
+                    ppu,
+                    bank,
+                    coarse_x + column,
+                    coarse_y,
+                    base_nametable,
+                    fine_y,
+                );
+                tile = Some((column, fetched));
+                fetched
+            }
+        };
+
+        if x < 8 && !ppu.mask.leftmost_8pxl_background() {
+            continue;
+        }
+
+        let value = tile_pixel(lower, upper, 7 - scrolled_x % 8);
+        pixels[x] = bg_palette_entry(ppu, palette, value);
+        opaque[x] = value != 0;
+    }
+}
+
+// returns the tile's pattern bytes for the row and its attribute palette
+fn fetch_background_tile(
+    ppu: &PPU,
+    bank: u16,
+    mut tile_column: usize,
+    coarse_y: usize,
+    mut nametable: usize,
+    fine_y: u16,
+) -> (u8, u8, u8) {
+    if tile_column >= 32 {
+        //crossed into the horizontally adjacent nametable
+        tile_column -= 32;
+        nametable ^= 1;
+    }
+
+    let nametable_addr = 0x2000 + nametable as u16 * 0x400;
+    let tile_idx =
+        ppu.fetch_nametable(nametable_addr + (coarse_y * 32 + tile_column) as u16) as u16;
+
+    // each attribute byte covers a 4x4 tile area split into four 2x2 quadrants
+    let attr_byte =
+        ppu.fetch_nametable(nametable_addr + 0x3c0 + ((coarse_y / 4) * 8 + tile_column / 4) as u16);
+    let shift = ((coarse_y % 4) / 2) * 4 + ((tile_column % 4) / 2) * 2;
+    let palette = (attr_byte >> shift) & 0b11;
+
+    let tile_addr = bank + tile_idx * 16 + fine_y;
+    let lower = ppu.fetch_chr(tile_addr, PatternFetch::Background);
+    let upper = ppu.fetch_chr(tile_addr + 8, PatternFetch::Background);
+    (lower, upper, palette)
+}
+
+// returns the x coordinate at which sprite 0 overlapped an opaque background pixel
+fn render_sprites_scanline(
+    ppu: &PPU,
+    y: usize,
+    sprites: &[u8],
+    pixels: &mut [u8; Frame::WIDTH],
+    bg_opaque: &[bool; Frame::WIDTH],
+) -> Option<usize> {
+    let height = ppu.ctrl.sprite_height();
+    let mut drawn = [false; Frame::WIDTH];
+    let mut sprite_zero_hit = None;
+
+    //lower oam index wins, so sprites are walked front to back and the first opaque pixel sticks
+    for &n in sprites {
+        let oam = &ppu.oam_data[n as usize * 4..n as usize * 4 + 4];
+        let tile_y = oam[0] as usize;
+        let tile_idx = oam[1];
+        let attributes = oam[2];
+        let tile_x = oam[3] as usize;
+
+        let flip_vertical = attributes & 0b1000_0000 != 0;
+        let flip_horizontal = attributes & 0b0100_0000 != 0;
+        let behind_background = attributes & 0b0010_0000 != 0;
+        let palette = attributes & 0b11;
+
+        //sprites are picked on the line before, and a mid-frame oam or $2000 write can
+        //move one out of range of this line since. such a sprite is skipped
+        let mut row = match y.checked_sub(tile_y + 1) {
+            Some(row) if row < height => row,
+            _ => continue,
+        };
+        if flip_vertical {
+            row = height - 1 - row;
+        }
+
+        let tile_addr = if height == 16 {
+            //8x16 sprites take the pattern table from bit 0 of the tile index
+            let bank = (tile_idx as u16 & 1) * 0x1000;
+            let mut tile = tile_idx as u16 & 0xfe;
+            if row >= 8 {
+                tile += 1;
+                row -= 8;
+            }
+            bank + tile * 16 + row as u16
+        } else {
+            ppu.ctrl.sprt_pattern_addr() + tile_idx as u16 * 16 + row as u16
+        };
+        let lower = ppu.fetch_chr(tile_addr, PatternFetch::Sprite);
+        let upper = ppu.fetch_chr(tile_addr + 8, PatternFetch::Sprite);
+
+        for px in 0..8 {
+            let x = tile_x + px;
+            if x >= Frame::WIDTH || drawn[x] {
+                continue;
+            }
+            if x < 8 && !ppu.mask.leftmost_8pxl_sprite() {
+                continue;
+            }
+            let bit = if flip_horizontal { px } else { 7 - px };
+            let value = tile_pixel(lower, upper, bit);
+            if value == 0 {
+                continue;
+            }
+            drawn[x] = true;
+
+            if n == 0
+                && bg_opaque[x]
+                && x != 255
+                && sprite_zero_hit.is_none()
+                && ppu.mask.show_background()
+            {
+                sprite_zero_hit = Some(x);
+            }
+
+            if behind_background && bg_opaque[x] {
+                continue;
+            }
+            pixels[x] = ppu.palette_table[(SPRITE_PALETTES + palette * 4 + value) as usize];
+        }
+    }
+    sprite_zero_hit
+}
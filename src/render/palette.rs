@@ -0,0 +1,84 @@
+// the 64 colors the 2C02 can output, indexed by the 6 bit values stored in palette ram
+#[rustfmt::skip]
+pub static SYSTEM_PALLETE: [(u8, u8, u8); 64] = [
+   (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96), (0xA1, 0x00, 0x5E),
+   (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00), (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00),
+   (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E), (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05),
+   (0x05, 0x05, 0x05), (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
+   (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00), (0xC4, 0x62, 0x00),
+   (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55), (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21),
+   (0x09, 0x09, 0x09), (0x09, 0x09, 0x09), (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF),
+   (0xD4, 0x80, 0xFF), (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
+   (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4), (0x05, 0xFB, 0xFF),
+   (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D), (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF),
+   (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB), (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0),
+   (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
+   (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
+];
+
+const PALETTE_SIZE: usize = 64;
+const EMPHASIS_VARIANTS: usize = 8;
+// how much a channel is dimmed when one of the other two is emphasised
+const EMPHASIS_ATTENUATION: f32 = 0.816328;
+
+// palette ram values mapped to rgb, once for each combination of the three
+// $2001 emphasis bits (red, green, blue) so 512 entries in total
+pub struct SystemPalette {
+    colors: Vec<(u8, u8, u8)>,
+}
+
+impl SystemPalette {
+    pub fn new() -> Self {
+        SystemPalette::with_emphasis(&SYSTEM_PALLETE)
+    }
+
+    // derives the emphasis variants from a plain 64 color palette
+    fn with_emphasis(base: &[(u8, u8, u8)]) -> Self {
+        let mut colors = Vec::with_capacity(PALETTE_SIZE * EMPHASIS_VARIANTS);
+        for emphasis in 0..EMPHASIS_VARIANTS {
+            let dim = |channel: u8, emphasised_bit: usize| {
+                let others = emphasis & !(1 << emphasised_bit);
+                if others != 0 {
+                    (channel as f32 * EMPHASIS_ATTENUATION) as u8
+                } else {
+                    channel
+                }
+            };
+            for &(r, g, b) in base {
+                colors.push((dim(r, 0), dim(g, 1), dim(b, 2)));
+            }
+        }
+        SystemPalette { colors }
+    }
+
+    // accepts the two common .pal layouts: 64 colors (192 bytes), or 64 colors
+    // for each of the 8 emphasis combinations (1536 bytes)
+    pub fn from_pal(data: &[u8]) -> Result<SystemPalette, String> {
+        let colors: Vec<(u8, u8, u8)> = data.chunks_exact(3).map(|c| (c[0], c[1], c[2])).collect();
+        match data.len() {
+            192 => Ok(SystemPalette::with_emphasis(&colors)),
+            1536 => Ok(SystemPalette { colors }),
+            len => Err(format!(
+                "Palette file must be 192 or 1536 bytes long, got {}",
+                len
+            )),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<SystemPalette, String> {
+        let data = std::fs::read(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+        SystemPalette::from_pal(&data)
+    }
+
+    // emphasis is the top three bits of $2001 shifted down
+    pub fn rgb(&self, palette_idx: u8, emphasis: u8) -> (u8, u8, u8) {
+        let idx = (emphasis as usize & 0b111) * PALETTE_SIZE + (palette_idx as usize & 0x3f);
+        self.colors[idx]
+    }
+}
+
+impl Default for SystemPalette {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,143 @@
+// minimal png encoder for screenshots: 8-bit rgb, no filtering, and the image
+// data wrapped in uncompressed (stored) deflate blocks so no compressor is needed
+
+use crate::checksum::crc32_update;
+use crate::render::frame::Frame;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+const COLOR_TYPE_RGB: u8 = 2;
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+// encodes tightly packed rgb pixels (3 bytes per pixel, row by row) as a png file
+pub fn encode(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(
+        rgb.len(),
+        width * height * 3,
+        "pixel data doesn't match size"
+    );
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    //bit depth, color type, compression, filter, interlace
+    header.extend_from_slice(&[8, COLOR_TYPE_RGB, 0, 0, 0]);
+
+    //each scanline is prefixed with its filter type, 0 = none
+    let row_len = width * 3;
+    let mut raw = Vec::with_capacity((row_len + 1) * height);
+    for row in rgb.chunks(row_len.max(1)).take(height) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+pub fn encode_frame(frame: &Frame) -> Vec<u8> {
+    encode(Frame::WIDTH, Frame::HEIGHT, &frame.data)
+}
+
+pub fn save_frame(frame: &Frame, path: &str) -> Result<(), String> {
+    std::fs::write(path, encode_frame(frame))
+        .map_err(|e| format!("Couldn't write screenshot {path}: {e}"))
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = !crc32_update(crc32_update(0xffff_ffff, kind), data);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    //deflate, 32k window, no preset dictionary, fastest compression level
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        //a zero length final block
+        out.extend_from_slice(&[1, 0x00, 0x00, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % MOD_ADLER;
+        b = (b + a) % MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::checksum::crc32;
+
+    // walks the chunk list, checking every crc, and returns (kind, data) pairs
+    fn chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        assert_eq!(png[..8], SIGNATURE);
+        let mut chunks = vec![];
+        let mut pos = 8;
+        while pos < png.len() {
+            let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            let kind: [u8; 4] = png[pos + 4..pos + 8].try_into().unwrap();
+            let data = png[pos + 8..pos + 8 + len].to_vec();
+            let crc = u32::from_be_bytes(png[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32(&png[pos + 4..pos + 8 + len]));
+            chunks.push((kind, data));
+            pos += 12 + len;
+        }
+        chunks
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn test_encode_layout() {
+        let pixels = [0xff, 0x00, 0x00, 0x00, 0xff, 0x00];
+        let png = encode(2, 1, &pixels);
+        let chunks = chunks(&png);
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, vec![b"IHDR", b"IDAT", b"IEND"]);
+
+        assert_eq!(chunks[0].1, vec![0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0]);
+
+        let idat = &chunks[1].1;
+        assert_eq!(idat[..2], [0x78, 0x01]);
+        //final stored block holding the filter byte plus both pixels
+        assert_eq!(idat[2..7], [1, 7, 0, !7, 0xff]);
+        assert_eq!(idat[7..14], [0, 0xff, 0, 0, 0, 0xff, 0]);
+        assert_eq!(idat[14..], adler32(&idat[7..14]).to_be_bytes());
+    }
+
+    #[test]
+    fn test_encode_splits_large_images_into_blocks() {
+        let frame = Frame::new();
+        let png = encode_frame(&frame);
+        let idat = &chunks(&png)[1].1;
+        let raw_len = (Frame::WIDTH * 3 + 1) * Frame::HEIGHT;
+        let blocks = raw_len.div_ceil(MAX_STORED_BLOCK);
+        assert_eq!(idat.len(), 2 + raw_len + blocks * 5 + 4);
+        //first block isn't final and is full sized
+        assert_eq!(idat[2..5], [0, 0xff, 0xff]);
+    }
+}
@@ -0,0 +1,232 @@
+// debug views of the ppu's graphics state, drawn into plain rgb images
+
+use crate::ppu::PPU;
+use crate::render::frame::Frame;
+use crate::render::tile_pixel;
+
+const TILE_SIZE: usize = 8;
+const TILES_PER_ROW: usize = 16;
+const PATTERN_TABLE_SIZE: usize = TILES_PER_ROW * TILE_SIZE;
+
+const NAMETABLE_COLUMNS: usize = 32;
+const NAMETABLE_ROWS: usize = 30;
+
+const SPRITES_PER_ROW: usize = 8;
+const SPRITE_CELL_WIDTH: usize = 16;
+// room for an 8x16 sprite plus the attribute strip underneath
+const SPRITE_CELL_HEIGHT: usize = 24;
+
+const PALETTE_SWATCH: usize = 16;
+
+const OUTLINE: (u8, u8, u8) = (0xff, 0x00, 0xff);
+const MARKER_ON: (u8, u8, u8) = (0xff, 0xff, 0xff);
+const MARKER_OFF: (u8, u8, u8) = (0x40, 0x40, 0x40);
+
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Image {
+            width,
+            height,
+            data: vec![0; width * height * 3],
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let base = (y * self.width + x) * 3;
+        self.data[base] = rgb.0;
+        self.data[base + 1] = rgb.1;
+        self.data[base + 2] = rgb.2;
+    }
+
+    fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, rgb: (u8, u8, u8)) {
+        for py in y..y + h {
+            for px in x..x + w {
+                self.set_pixel(px, py, rgb);
+            }
+        }
+    }
+}
+
+fn palette_rgb(ppu: &PPU, palette_entry: usize) -> (u8, u8, u8) {
+    ppu.system_palette.rgb(ppu.palette_table[palette_entry], 0)
+}
+
+// palette 0-3 are the background palettes, 4-7 the sprite ones
+fn tile_color(ppu: &PPU, palette: u8, value: u8) -> (u8, u8, u8) {
+    if value == 0 {
+        palette_rgb(ppu, 0)
+    } else {
+        palette_rgb(ppu, (palette as usize & 0b111) * 4 + value as usize)
+    }
+}
+
+// flip is (horizontal, vertical)
+fn draw_tile(
+    ppu: &PPU,
+    image: &mut Image,
+    tile_addr: u16,
+    palette: u8,
+    (x, y): (usize, usize),
+    (flip_horizontal, flip_vertical): (bool, bool),
+) {
+    for row in 0..TILE_SIZE {
+        let lower = ppu.read_chr(tile_addr + row as u16);
+        let upper = ppu.read_chr(tile_addr + row as u16 + 8);
+        let py = if flip_vertical { 7 - row } else { row };
+        for col in 0..TILE_SIZE {
+            let value = tile_pixel(lower, upper, 7 - col);
+            let px = if flip_horizontal { 7 - col } else { col };
+            image.set_pixel(x + px, y + py, tile_color(ppu, palette, value));
+        }
+    }
+}
+
+// both pattern tables side by side, $0000 on the left and $1000 on the right
+pub fn render_pattern_tables(ppu: &PPU, palette: u8) -> Image {
+    let mut image = Image::new(PATTERN_TABLE_SIZE * 2, PATTERN_TABLE_SIZE);
+    for bank in 0..2 {
+        for tile in 0..256 {
+            let tile_addr = (bank * 0x1000 + tile * 16) as u16;
+            let x = bank * PATTERN_TABLE_SIZE + (tile % TILES_PER_ROW) * TILE_SIZE;
+            let y = (tile / TILES_PER_ROW) * TILE_SIZE;
+            draw_tile(ppu, &mut image, tile_addr, palette, (x, y), (false, false));
+        }
+    }
+    image
+}
+
+// the four nametables in a 2x2 layout, with the visible 256x240 window outlined
+// where the scroll registers currently place it
+pub fn render_nametables(ppu: &PPU) -> Image {
+    let width = Frame::WIDTH * 2;
+    let height = Frame::HEIGHT * 2;
+    let mut image = Image::new(width, height);
+    let bank = ppu.ctrl.bknd_pattern_addr();
+
+    for nametable in 0..4u16 {
+        let nametable_addr = 0x2000 + nametable * 0x400;
+        let origin_x = (nametable as usize & 1) * Frame::WIDTH;
+        let origin_y = (nametable as usize >> 1) * Frame::HEIGHT;
+
+        for row in 0..NAMETABLE_ROWS {
+            for column in 0..NAMETABLE_COLUMNS {
+                let tile_idx =
+                    ppu.read_nametable(nametable_addr + (row * NAMETABLE_COLUMNS + column) as u16);
+                let attr_byte = ppu
+                    .read_nametable(nametable_addr + 0x3c0 + ((row / 4) * 8 + column / 4) as u16);
+                let shift = ((row % 4) / 2) * 4 + ((column % 4) / 2) * 2;
+                let palette = (attr_byte >> shift) & 0b11;
+
+                let position = (origin_x + column * TILE_SIZE, origin_y + row * TILE_SIZE);
+                let tile_addr = bank + tile_idx as u16 * 16;
+                draw_tile(
+                    ppu,
+                    &mut image,
+                    tile_addr,
+                    palette,
+                    position,
+                    (false, false),
+                );
+            }
+        }
+    }
+
+    let temp = ppu.scroll.temp_addr();
+    let scroll_x = ((temp >> 10) & 1) as usize * Frame::WIDTH
+        + (temp & 0b11111) as usize * TILE_SIZE
+        + ppu.scroll.fine_x as usize;
+    let scroll_y = ((temp >> 11) & 1) as usize * Frame::HEIGHT
+        + ((temp >> 5) & 0b11111) as usize * TILE_SIZE
+        + ((temp >> 12) & 0b111) as usize;
+    for dx in 0..Frame::WIDTH {
+        let x = (scroll_x + dx) % width;
+        image.set_pixel(x, scroll_y % height, OUTLINE);
+        image.set_pixel(x, (scroll_y + Frame::HEIGHT - 1) % height, OUTLINE);
+    }
+    for dy in 0..Frame::HEIGHT {
+        let y = (scroll_y + dy) % height;
+        image.set_pixel(scroll_x % width, y, OUTLINE);
+        image.set_pixel((scroll_x + Frame::WIDTH - 1) % width, y, OUTLINE);
+    }
+    image
+}
+
+// all 64 sprites in oam order, 8 per row. under each sprite a strip shows its
+// attributes: the sprite palette, then markers for behind-background priority,
+// horizontal flip and vertical flip
+pub fn render_oam(ppu: &PPU) -> Image {
+    let mut image = Image::new(
+        SPRITES_PER_ROW * SPRITE_CELL_WIDTH,
+        (64 / SPRITES_PER_ROW) * SPRITE_CELL_HEIGHT,
+    );
+    let tall = ppu.ctrl.sprite_height() == 16;
+
+    for n in 0..64 {
+        let oam = &ppu.oam_data[n * 4..n * 4 + 4];
+        let tile_idx = oam[1] as u16;
+        let attributes = oam[2];
+        let flip_vertical = attributes & 0b1000_0000 != 0;
+        let flip_horizontal = attributes & 0b0100_0000 != 0;
+        let behind_background = attributes & 0b0010_0000 != 0;
+        let palette = 4 + (attributes & 0b11);
+        let flip = (flip_horizontal, flip_vertical);
+
+        let x = (n % SPRITES_PER_ROW) * SPRITE_CELL_WIDTH + 4;
+        let y = (n / SPRITES_PER_ROW) * SPRITE_CELL_HEIGHT;
+
+        if tall {
+            let bank = (tile_idx & 1) * 0x1000;
+            let top = bank + (tile_idx & 0xfe) * 16;
+            let (first, second) = if flip_vertical {
+                (top + 16, top)
+            } else {
+                (top, top + 16)
+            };
+            draw_tile(ppu, &mut image, first, palette, (x, y), flip);
+            draw_tile(ppu, &mut image, second, palette, (x, y + 8), flip);
+        } else {
+            let tile_addr = ppu.ctrl.sprt_pattern_addr() + tile_idx * 16;
+            draw_tile(ppu, &mut image, tile_addr, palette, (x, y), flip);
+        }
+
+        let strip_y = y + 18;
+        for value in 1..4 {
+            let swatch = tile_color(ppu, palette, value);
+            image.fill_rect(x - 4 + (value as usize - 1) * 2, strip_y, 2, 4, swatch);
+        }
+        for (i, on) in [behind_background, flip_horizontal, flip_vertical]
+            .iter()
+            .enumerate()
+        {
+            let marker = if *on { MARKER_ON } else { MARKER_OFF };
+            image.fill_rect(x + 3 + i * 3, strip_y, 2, 4, marker);
+        }
+    }
+    image
+}
+
+// the 32 palette ram entries, background palettes on the top row and sprite ones below
+pub fn render_palette(ppu: &PPU) -> Image {
+    let mut image = Image::new(16 * PALETTE_SWATCH, 2 * PALETTE_SWATCH);
+    for entry in 0..32 {
+        let x = (entry % 16) * PALETTE_SWATCH;
+        let y = (entry / 16) * PALETTE_SWATCH;
+        image.fill_rect(
+            x,
+            y,
+            PALETTE_SWATCH,
+            PALETTE_SWATCH,
+            palette_rgb(ppu, entry),
+        );
+    }
+    image
+}
@@ -0,0 +1,95 @@
+// battery backed prg ram is kept in a .sav file next to the rom, holding the raw ram
+// contents. that's the layout most emulators use, so saves carry over between them.
+
+use std::path::{Path, PathBuf};
+
+// the name carries the crc32 of the rom's prg and chr data, so a save belongs to the
+// game it was made with. another rom put in place under the same file name won't load it
+pub fn path_for_rom(rom_path: &Path, crc32: u32) -> PathBuf {
+    rom_path.with_extension(format!("{crc32:08x}.sav"))
+}
+
+pub struct SaveFile {
+    path: PathBuf,
+    //what the file holds, so unchanged ram isn't written again
+    written: Vec<u8>,
+}
+
+impl SaveFile {
+    pub fn new(path: PathBuf) -> Self {
+        SaveFile {
+            path,
+            written: Vec::new(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // the saved ram, or None when there is no save yet
+    pub fn load(&mut self) -> Result<Option<Vec<u8>>, String> {
+        match std::fs::read(&self.path) {
+            Ok(data) => {
+                self.written = data.clone();
+                Ok(Some(data))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Couldn't read save {}: {e}", self.path.display())),
+        }
+    }
+
+    // writes ram out if it changed since the last load or save, returning whether it did.
+    // the data goes to a temporary file first, so a crash mid-write can't truncate the save
+    pub fn save(&mut self, ram: &[u8]) -> Result<bool, String> {
+        if ram == self.written.as_slice() {
+            return Ok(false);
+        }
+        let temp = self.path.with_extension("sav.tmp");
+        std::fs::write(&temp, ram)
+            .and_then(|_| std::fs::rename(&temp, &self.path))
+            .map_err(|e| format!("Couldn't write save {}: {e}", self.path.display()))?;
+        self.written = ram.to_vec();
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("nes-rust-{}-{name}.sav", std::process::id()))
+    }
+
+    #[test]
+    fn test_path_for_rom() {
+        assert_eq!(
+            path_for_rom(Path::new("roms/zelda.nes"), 0x3fe2_72fb),
+            PathBuf::from("roms/zelda.3fe272fb.sav")
+        );
+    }
+
+    #[test]
+    fn test_missing_save_loads_as_none() {
+        let mut save = SaveFile::new(temp_path("missing"));
+        assert_eq!(save.load(), Ok(None));
+    }
+
+    #[test]
+    fn test_save_round_trip_skips_unchanged_ram() {
+        let path = temp_path("round-trip");
+        let mut save = SaveFile::new(path.clone());
+        let mut ram = vec![0u8; 0x2000];
+        ram[0x10] = 0x42;
+
+        assert_eq!(save.save(&ram), Ok(true));
+        assert_eq!(save.save(&ram), Ok(false));
+        assert_eq!(SaveFile::new(path.clone()).load(), Ok(Some(ram.clone())));
+
+        ram[0x11] = 0x43;
+        assert_eq!(save.save(&ram), Ok(true));
+        assert_eq!(std::fs::read(&path).unwrap(), ram);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+// screenshot regression tests: every case in tests/screenshots.txt runs a rom headlessly
+// for a number of frames, optionally holding buttons on the first controller, and
+// compares a crc32 of the final frame with the stored hash.
+//
+// set NES_BLESS=1 to write the current hashes back into the manifest instead of failing.
+// on a mismatch the frame that was produced is written to
+// target/screenshot-failures/<case>.png so it can be inspected.
+//
+// every rom a case names has to be in the tree, a missing one fails the test rather
+// than being skipped.
+
+use nes_rust::bus::Bus;
+use nes_rust::cartridge::Rom;
+use nes_rust::checksum::crc32;
+use nes_rust::cpu::CPU;
+use nes_rust::joypad::JoypadButton;
+use nes_rust::render::png;
+use std::path::{Path, PathBuf};
+
+const MANIFEST: &str = "tests/screenshots.txt";
+const FAILURE_DIR: &str = "target/screenshot-failures";
+
+// buttons held from frame `first` through frame `last`, inclusive
+struct Input {
+    buttons: JoypadButton,
+    first: u64,
+    last: u64,
+}
+
+struct Case {
+    name: String,
+    rom: String,
+    frames: u64,
+    hash: Option<u32>,
+    inputs: Vec<Input>,
+}
+
+impl Case {
+    fn buttons_at(&self, frame: u64) -> JoypadButton {
+        self.inputs
+            .iter()
+            .filter(|input| (input.first..=input.last).contains(&frame))
+            .fold(JoypadButton::empty(), |held, input| held | input.buttons)
+    }
+}
+
+fn parse_button(name: &str) -> Result<JoypadButton, String> {
+    Ok(match name {
+        "a" => JoypadButton::BUTTON_A,
+        "b" => JoypadButton::BUTTON_B,
+        "select" => JoypadButton::SELECT,
+        "start" => JoypadButton::START,
+        "up" => JoypadButton::UP,
+        "down" => JoypadButton::DOWN,
+        "left" => JoypadButton::LEFT,
+        "right" => JoypadButton::RIGHT,
+        _ => return Err(format!("unknown button {name}")),
+    })
+}
+
+// `start+a@30` holds start and a during frame 30, `right@10-40` holds right for frames 10 to 40
+fn parse_input(input: &str) -> Result<Input, String> {
+    let (buttons, frames) = input
+        .split_once('@')
+        .ok_or(format!("input {input} is missing @frame"))?;
+    let buttons = buttons
+        .split('+')
+        .map(parse_button)
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .fold(JoypadButton::empty(), |held, button| held | button);
+    let (first, last) = frames.split_once('-').unwrap_or((frames, frames));
+    let frame = |s: &str| s.parse::<u64>().map_err(|e| format!("bad frame {s}: {e}"));
+    Ok(Input {
+        buttons,
+        first: frame(first)?,
+        last: frame(last)?,
+    })
+}
+
+// name rom frames hash [inputs...], where a hash of `-` means not blessed yet
+fn parse_case(line: &str) -> Result<Case, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 4 {
+        return Err(format!(
+            "expected `name rom frames hash [inputs...]`, got `{line}`"
+        ));
+    }
+    let hash = match fields[3] {
+        "-" => None,
+        hash => Some(u32::from_str_radix(hash, 16).map_err(|e| format!("bad hash {hash}: {e}"))?),
+    };
+    Ok(Case {
+        name: fields[0].to_string(),
+        rom: fields[1].to_string(),
+        frames: fields[2]
+            .parse()
+            .map_err(|e| format!("bad frame count {}: {e}", fields[2]))?,
+        hash,
+        inputs: fields[4..]
+            .iter()
+            .map(|input| parse_input(input))
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+fn run_case(case: &Case, rom_path: &Path) -> Result<Vec<u8>, String> {
+    let bytes = std::fs::read(rom_path).map_err(|e| format!("couldn't read rom: {e}"))?;
+    let rom = Rom::new(&bytes).map_err(|e| e.to_string())?;
+
+    let mut cpu = CPU::new(Bus::new(rom));
+    cpu.reset();
+    for frame in 1..=case.frames {
+        let joypad = cpu.bus.joypad1_mut();
+        joypad.set_button_pressed_status(JoypadButton::all(), false);
+        joypad.set_button_pressed_status(case.buttons_at(frame), true);
+
+        cpu.run_until(|cpu| cpu.bus.frames() >= frame);
+        if cpu.bus.frames() < frame {
+            return Err(format!("program stopped during frame {frame}"));
+        }
+    }
+    Ok(cpu.bus.ppu().frame.data.clone())
+}
+
+fn save_failure(root: &Path, name: &str, frame: &[u8]) -> PathBuf {
+    let dir = root.join(FAILURE_DIR);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{name}.png"));
+    let png = png::encode(
+        nes_rust::render::frame::Frame::WIDTH,
+        nes_rust::render::frame::Frame::HEIGHT,
+        frame,
+    );
+    std::fs::write(&path, png).unwrap();
+    path
+}
+
+#[test]
+fn test_screenshots_match_manifest() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let manifest_path = root.join(MANIFEST);
+    let manifest = std::fs::read_to_string(&manifest_path).unwrap();
+    let bless = std::env::var("NES_BLESS").is_ok_and(|v| !v.is_empty() && v != "0");
+
+    let mut blessed = Vec::new();
+    let mut failures = Vec::new();
+    let mut passed = 0;
+    for line in manifest.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            blessed.push(line.to_string());
+            continue;
+        }
+        let case = parse_case(trimmed).unwrap();
+
+        let rom_path = root.join(&case.rom);
+        if !rom_path.exists() {
+            failures.push(format!("{}: {} not found", case.name, case.rom));
+            blessed.push(line.to_string());
+            continue;
+        }
+
+        let frame = match run_case(&case, &rom_path) {
+            Ok(frame) => frame,
+            Err(e) => {
+                failures.push(format!("{}: {e}", case.name));
+                blessed.push(line.to_string());
+                continue;
+            }
+        };
+        let hash = crc32(&frame);
+
+        if bless {
+            let mut fields: Vec<String> = trimmed.split_whitespace().map(String::from).collect();
+            fields[3] = format!("{hash:08x}");
+            blessed.push(fields.join(" "));
+        } else if case.hash != Some(hash) {
+            let path = save_failure(root, &case.name, &frame);
+            let expected = case
+                .hash
+                .map_or("nothing (not blessed)".to_string(), |h| format!("{h:08x}"));
+            failures.push(format!(
+                "{}: expected {expected}, got {hash:08x}, frame saved to {}",
+                case.name,
+                path.display()
+            ));
+        } else {
+            passed += 1;
+        }
+    }
+    println!("{passed} screenshots matched, {} failed", failures.len());
+
+    if bless {
+        std::fs::write(&manifest_path, blessed.join("\n") + "\n").unwrap();
+    }
+    assert!(
+        failures.is_empty(),
+        "screenshot mismatches (rerun with NES_BLESS=1 if the change is intended):\n{}",
+        failures.join("\n")
+    );
+}
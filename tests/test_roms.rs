@@ -0,0 +1,238 @@
+// runs test roms that report through the $6000 status protocol used by blargg's
+// newer tests (instr_test, cpu_interrupts, ppu_vbl_nmi, apu_test, ...):
+//   $6000        status: $80 running, $81 reset requested, below $80 the final result
+//   $6001-$6003  $de $b0 $61 once the status byte is valid
+//   $6004-       zero terminated text output
+// every .nes under tests/roms/status (or $NES_TEST_ROMS) is run, a result of 0 passes.
+// the roms aren't checked in, so the test is ignored by default and fails when run
+// without any: cargo test --test test_roms -- --ignored
+
+use nes_rust::bus::Bus;
+use nes_rust::cartridge::Rom;
+use nes_rust::cpu::{Mem, CPU};
+use std::path::{Path, PathBuf};
+
+const ROM_DIR: &str = "tests/roms/status";
+
+const STATUS: u16 = 0x6000;
+const SIGNATURE: u16 = 0x6001;
+const TEXT: u16 = 0x6004;
+const TEXT_END: u16 = 0x7fff;
+
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_RESET: u8 = 0x81;
+const VALID_SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+
+// the rom asks for the reset button to be held for at least 100ms
+const RESET_DELAY_FRAMES: u64 = 6;
+// about a minute of emulated time
+const TIMEOUT_FRAMES: u64 = 60 * 60;
+
+struct TestResult {
+    code: u8,
+    message: String,
+}
+
+fn status(cpu: &mut CPU) -> Option<u8> {
+    let signature = [
+        cpu.mem_read(SIGNATURE),
+        cpu.mem_read(SIGNATURE + 1),
+        cpu.mem_read(SIGNATURE + 2),
+    ];
+    (signature == VALID_SIGNATURE).then(|| cpu.mem_read(STATUS))
+}
+
+fn message(cpu: &mut CPU) -> String {
+    let mut text = Vec::new();
+    for addr in TEXT..=TEXT_END {
+        match cpu.mem_read(addr) {
+            0 => break,
+            byte => text.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&text).trim_end().to_string()
+}
+
+fn run_test_rom(bytes: &[u8]) -> Result<TestResult, String> {
+    let rom = Rom::new(bytes).map_err(|e| e.to_string())?;
+    let mut cpu = CPU::new(Bus::new(rom));
+    cpu.reset();
+
+    let mut reset_at = None;
+    let mut frame = 0;
+    while frame < TIMEOUT_FRAMES {
+        frame += 1;
+        cpu.run_until(|cpu| cpu.bus.frames() >= frame);
+        if cpu.bus.frames() < frame {
+            return Err(format!(
+                "program stopped during frame {frame}: {}",
+                message(&mut cpu)
+            ));
+        }
+
+        match status(&mut cpu) {
+            None | Some(STATUS_RUNNING) => {}
+            Some(STATUS_RESET) => {
+                let requested = *reset_at.get_or_insert(frame);
+                if frame - requested >= RESET_DELAY_FRAMES {
+                    reset_at = None;
+                    cpu.reset();
+                    //the rom sets the status again after it restarts
+                    cpu.mem_write(STATUS, STATUS_RUNNING);
+                }
+            }
+            Some(code) => {
+                return Ok(TestResult {
+                    code,
+                    message: message(&mut cpu),
+                })
+            }
+        }
+    }
+    Err(format!(
+        "timed out after {TIMEOUT_FRAMES} frames: {}",
+        message(&mut cpu)
+    ))
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path.extension().is_some_and(|ext| ext == "nes") {
+            roms.push(path);
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs status protocol roms in tests/roms/status or $NES_TEST_ROMS"]
+fn test_status_protocol_roms() {
+    let dir = std::env::var("NES_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| Path::new(env!("CARGO_MANIFEST_DIR")).join(ROM_DIR));
+    let mut roms = Vec::new();
+    find_roms(&dir, &mut roms);
+    roms.sort();
+    assert!(!roms.is_empty(), "no test roms found in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for path in roms {
+        let name = path
+            .strip_prefix(&dir)
+            .unwrap_or(&path)
+            .display()
+            .to_string();
+        let bytes = std::fs::read(&path).unwrap();
+        match run_test_rom(&bytes) {
+            Ok(TestResult { code: 0, message }) => println!("{name}: passed\n{message}"),
+            Ok(TestResult { code, message }) => {
+                println!("{name}: failed with code {code}\n{message}");
+                failures.push(format!("{name}: code {code}"));
+            }
+            Err(e) => {
+                println!("{name}: {e}");
+                failures.push(format!("{name}: {e}"));
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "test roms failed:\n{}",
+        failures.join("\n")
+    );
+}
+
+fn store(code: &mut Vec<u8>, value: u8, addr: u16) {
+    //lda #value, sta addr
+    code.extend_from_slice(&[0xa9, value, 0x8d, addr as u8, (addr >> 8) as u8]);
+}
+
+// loops forever, for code that starts at `base`
+fn spin(code: &mut Vec<u8>, base: u16) {
+    //jmp to itself
+    let here = base + code.len() as u16;
+    code.extend_from_slice(&[0x4c, here as u8, (here >> 8) as u8]);
+}
+
+// writes the signature, the message and finally the result code
+fn report(code: &mut Vec<u8>, result: u8, text: &str) {
+    for (i, byte) in VALID_SIGNATURE.iter().enumerate() {
+        store(code, *byte, SIGNATURE + i as u16);
+    }
+    for (i, byte) in text.bytes().chain(std::iter::once(0)).enumerate() {
+        store(code, byte, TEXT + i as u16);
+    }
+    store(code, result, STATUS);
+}
+
+// an nrom image with a 16KB prg bank whose vectors all point at $8000
+fn nrom(trainer: Option<&[u8]>, mut prg: Vec<u8>) -> Vec<u8> {
+    prg.resize(0x4000, 0xea);
+    //nmi, reset and irq vectors
+    prg[0x3ffa..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+
+    let flags6 = if trainer.is_some() { 0b100 } else { 0 };
+    let mut raw = vec![
+        b'N', b'E', b'S', 0x1a, 1, 1, flags6, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    if let Some(trainer) = trainer {
+        raw.extend_from_slice(trainer);
+    }
+    raw.extend(prg);
+    raw.extend(vec![0; 0x2000]);
+    raw
+}
+
+// a small nrom image exercising the protocol: the first run asks for a reset, the
+// second one reports a result with a message
+fn protocol_rom(code: u8, text: &str) -> Vec<u8> {
+    let mut prg = Vec::new();
+    for (i, byte) in VALID_SIGNATURE.iter().enumerate() {
+        store(&mut prg, *byte, SIGNATURE + i as u16);
+    }
+    //lda $6100, bne second_run, inc $6100
+    prg.extend_from_slice(&[0xad, 0x00, 0x61, 0xd0, 0x00, 0xee, 0x00, 0x61]);
+    let branch = prg.len() - 4;
+    store(&mut prg, STATUS_RESET, STATUS);
+    spin(&mut prg, 0x8000);
+
+    prg[branch] = (prg.len() - (branch + 1)) as u8;
+    report(&mut prg, code, text);
+    spin(&mut prg, 0x8000);
+    nrom(None, prg)
+}
+
+// the program starts by jumping into the trainer, which reports the pass. without the
+// trainer in place it runs into the zeroed ram at $7000 and stops on a BRK
+fn trainer_rom() -> Vec<u8> {
+    let mut trainer = Vec::new();
+    report(&mut trainer, 0, "Trainer ran");
+    spin(&mut trainer, 0x7000);
+    trainer.resize(512, 0);
+
+    //jmp $7000
+    nrom(Some(&trainer), vec![0x4c, 0x00, 0x70])
+}
+
+#[test]
+fn test_runner_follows_reset_request_and_reads_result() {
+    let result = run_test_rom(&protocol_rom(0, "Passed\n")).unwrap();
+    assert_eq!(result.code, 0);
+    assert_eq!(result.message, "Passed");
+
+    let result = run_test_rom(&protocol_rom(3, "Failed #3")).unwrap();
+    assert_eq!(result.code, 3);
+    assert_eq!(result.message, "Failed #3");
+}
+
+#[test]
+fn test_trainer_is_loaded_at_7000() {
+    let result = run_test_rom(&trainer_rom()).unwrap();
+    assert_eq!(result.code, 0);
+    assert_eq!(result.message, "Trainer ran");
+}